@@ -0,0 +1,427 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio, exit};
+use std::thread;
+use std::time::Duration;
+
+use crate::{pool, shell, sys};
+
+
+/// Subcommands of the daemon and of its clients (e.g. `runner queue`).
+pub(crate) const SUBCOMMANDS: [&str; 5] = ["daemon", "submit", "queue", "cancel", "logs"];
+// Name of the shared pool of the slots of the daemon.
+const DAEMON_POOL: &str = "daemon";
+// Separator of the fields of the requests, which cannot be in arguments.
+const FIELD_SEPARATOR: char = '\0';
+
+
+/// Sweep submitted to the daemon, which is run by a runner process.
+struct Sweep {
+    id: usize,
+    priority: i32,
+    cwd: String,
+    env: Vec<(String, String)>,
+    args: Vec<String>,
+    child: Option<Child>,
+    cancelled: bool,
+    // Final state of the sweep (e.g. done, or cancelled).
+    end_state: Option<String>,
+}
+
+
+impl Sweep {
+    fn get_state(&self) -> String {
+        match (&self.end_state, &self.child) {
+            (Some(end_state), _) => end_state.clone(),
+            (None, Some(_)) if self.cancelled => "cancelling".to_string(),
+            (None, Some(_)) => "running".to_string(),
+            (None, None) => "queued".to_string(),
+        }
+    }
+}
+
+
+/// Parse the value of an option of the subcommands, which is the next
+/// argument.
+fn parse_option_value(args: &[String], i: &mut usize, option: &str) -> String {
+    match args.get(*i + 1) {
+        Some(value) => {
+            *i += 2;
+            value.clone()
+        },
+        None => {
+            println!("Error: {} requires an argument.", option);
+            exit(1);
+        }
+    }
+}
+
+
+/// Parse a number of a subcommand option.
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> T {
+    match value.parse::<T>() {
+        Ok(number) => number,
+        Err(_) => {
+            println!("Error: {} requires an integer argument.", option);
+            exit(1);
+        }
+    }
+}
+
+
+/// Run a subcommand of the daemon (e.g. `runner submit <args>`).
+pub(crate) fn run_subcommand(subcommand: &str, args: &[String]) {
+    let mut daemon_dir = env::temp_dir().join("runner-daemon");
+    let mut runners = 1;
+    let mut priority = 0;
+    let mut i = 0;
+    loop {
+        // The options of other subcommands are left (e.g. the --runners of a
+        // submitted sweep).
+        let is_option = |option: &str| args.get(i).is_some_and(|x| x == option);
+        if is_option("--runner-daemon-dir") {
+            daemon_dir = PathBuf::from(parse_option_value(args, &mut i, "--runner-daemon-dir"));
+        } else if subcommand == "daemon" && is_option("--runners") {
+            runners = parse_number("--runners", &parse_option_value(args, &mut i, "--runners"));
+            if runners == 0 {
+                println!("Error: --runners requires an integer argument greater than 0.");
+                exit(1);
+            }
+        } else if subcommand == "submit" && is_option("--runner-priority") {
+            priority = parse_number("--runner-priority", &parse_option_value(args, &mut i, "--runner-priority"));
+        } else {
+            break;
+        }
+    }
+    let args = &args[i..];
+    match subcommand {
+        "daemon" => run_daemon(&daemon_dir, runners),
+        "submit" => {
+            if args.is_empty() {
+                println!("Error: submit requires the arguments of runner.");
+                exit(1);
+            }
+            submit_sweep(&daemon_dir, priority, args);
+        },
+        "queue" => send_request(&daemon_dir, &["queue".to_string()]),
+        _ => {
+            if args.len() != 1 {
+                println!("Error: {} requires the id of a sweep.", subcommand);
+                exit(1);
+            }
+            send_request(&daemon_dir, &[subcommand.to_string(), args[0].clone()]);
+        },
+    }
+}
+
+
+/// Check the arguments of the sweep with the usual pipeline, as with
+/// `--runner-info` and the pool of the daemon, and submit the sweep with the current directory and
+/// environment.
+fn submit_sweep(daemon_dir: &Path, priority: i32, args: &[String]) {
+    // The sweep is run with the pool of the daemon.
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["--runner-info", "--runner-pool", &format!("{}:1", DAEMON_POOL)])
+        .args(args)
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if !output.status.success() => {
+            print!("{}", String::from_utf8_lossy(&output.stdout));
+            exit(1);
+        },
+        Ok(_) => (),
+        Err(e) => {
+            println!("Error: couldn't check the arguments: {}", e);
+            exit(1);
+        }
+    }
+    let cwd = env::current_dir().unwrap().to_string_lossy().to_string();
+    let env_vars: Vec<String> = env::vars().map(|(k, v)| format!("{}={}", k, v)).collect();
+    let mut fields = vec!["submit".to_string(), priority.to_string(), cwd, env_vars.len().to_string()];
+    fields.extend(env_vars);
+    fields.extend(args.iter().cloned());
+    send_request(daemon_dir, &fields);
+}
+
+
+/// Send a request to the daemon and print its response.
+fn send_request(daemon_dir: &Path, fields: &[String]) {
+    let socket_path = daemon_dir.join("runner.sock");
+    let mut stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Error: couldn't connect to the daemon at {}: {}", socket_path.display(), e);
+            exit(1);
+        }
+    };
+    let request = fields.join(&FIELD_SEPARATOR.to_string());
+    let mut response = String::new();
+    let result = stream.write_all(request.as_bytes())
+        .and_then(|_| stream.shutdown(std::net::Shutdown::Write))
+        .and_then(|_| stream.read_to_string(&mut response));
+    if let Err(e) = result {
+        println!("Error: couldn't send the request to the daemon: {}", e);
+        exit(1);
+    }
+    print!("{}", response);
+    if response.starts_with("Error:") {
+        exit(1);
+    }
+}
+
+
+/// Run the daemon, which listens on the socket of its directory and runs the
+/// submitted sweeps, by priority and then in order, sharing its slots.
+fn run_daemon(daemon_dir: &Path, runners: usize) {
+    let logs_dir = daemon_dir.join("logs");
+    let holds_dir = daemon_dir.join("holds");
+    let pools_dir = daemon_dir.join("pools");
+    for dir in [&logs_dir, &holds_dir] {
+        if let Err(e) = fs::create_dir_all(dir) {
+            println!("Error: couldn't create {}: {}", dir.display(), e);
+            exit(1);
+        }
+    }
+    // Only the user of the daemon can submit sweeps, which run as this user.
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(e) = fs::set_permissions(daemon_dir, fs::Permissions::from_mode(0o700)) {
+            println!("Error: couldn't make {} private: {}", daemon_dir.display(), e);
+            exit(1);
+        }
+    }
+    let daemon_pool = pool::parse_shared_pool(&format!("{}:{}", DAEMON_POOL, runners), &pools_dir);
+    pool::create_pool_dir(&daemon_pool);
+    // The socket of a dead daemon is replaced.
+    let socket_path = daemon_dir.join("runner.sock");
+    if socket_path.exists() {
+        if UnixStream::connect(&socket_path).is_ok() {
+            println!("Error: a daemon is already running at {}.", socket_path.display());
+            exit(1);
+        }
+        let _ = fs::remove_file(&socket_path);
+    }
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Error: couldn't listen at {}: {}", socket_path.display(), e);
+            exit(1);
+        }
+    };
+    listener.set_nonblocking(true).unwrap();
+    sys::catch_interrupts();
+    println!("Daemon listening at {} with {} runners.", socket_path.display(), runners);
+
+    let mut sweeps: Vec<Sweep> = Vec::new();
+    while sys::get_interrupts() == 0 {
+        match listener.accept() {
+            Ok((stream, _)) => handle_request(stream, &mut sweeps, &logs_dir),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => println!("Error: couldn't accept a request: {}", e),
+        }
+        //// Finished sweeps.
+        for sweep in sweeps.iter_mut().filter(|x| x.end_state.is_none()) {
+            let status = match sweep.child.as_mut().map(|x| x.try_wait()) {
+                Some(Ok(Some(status))) => status,
+                _ => continue,
+            };
+            let end_state = if sweep.cancelled {
+                "cancelled".to_string()
+            } else if status.success() {
+                "done".to_string()
+            } else {
+                format!("failed ({})", status)
+            };
+            println!("Sweep {} {}.", sweep.id, end_state);
+            sweep.end_state = Some(end_state);
+        }
+        //// The next sweep is started when a slot is free, and no started
+        //// sweep is waiting for one, so they do not compete for the slots.
+        let is_waiting = sweeps.iter()
+            .filter(|x| x.child.is_some() && x.end_state.is_none())
+            .any(|x| is_holding(&holds_dir, x.id));
+        let next_sweep = sweeps.iter_mut()
+            .filter(|x| x.child.is_none() && x.end_state.is_none())
+            .min_by_key(|x| (-x.priority, x.id));
+        if let Some(sweep) = next_sweep.filter(|_| !is_waiting) {
+            if pool::try_lock_slot(&daemon_pool).is_some() {
+                start_sweep(sweep, &daemon_pool, &pools_dir, &holds_dir, &logs_dir);
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    //// Interrupted: stop the running sweeps.
+    println!("Interrupted: stopping the sweeps...");
+    for sweep in &mut sweeps {
+        if let (Some(child), None) = (&mut sweep.child, &sweep.end_state) {
+            sys::signal_process_group(child, sys::SIGTERM);
+            let _ = child.wait();
+        }
+    }
+    let _ = fs::remove_file(&socket_path);
+}
+
+
+/// Whether the launches of a started sweep are held (e.g. its runs wait for a
+/// slot), which is assumed until its runner writes its hold file, after
+/// locking the slot of its first run.
+fn is_holding(holds_dir: &Path, id: usize) -> bool {
+    match fs::read_to_string(holds_dir.join(id.to_string())) {
+        Ok(content) => !content.trim().is_empty(),
+        Err(_) => true,
+    }
+}
+
+
+/// Start a sweep with a runner process in its own process group, so that it
+/// can be cancelled with its commands.
+fn start_sweep(
+    sweep: &mut Sweep,
+    daemon_pool: &pool::SharedPool,
+    pools_dir: &Path,
+    holds_dir: &Path,
+    logs_dir: &Path,
+) {
+    let log_path = logs_dir.join(format!("{}.log", sweep.id));
+    let log_file = match File::create(&log_path) {
+        Ok(log_file) => log_file,
+        Err(e) => {
+            println!("Error: couldn't create {}: {}", log_path.display(), e);
+            sweep.end_state = Some("failed (no log)".to_string());
+            return;
+        }
+    };
+    // The file of a previous daemon with the same sweep ID is replaced.
+    let hold_path = holds_dir.join(sweep.id.to_string());
+    let _ = fs::remove_file(&hold_path);
+    let mut command_obj = Command::new(env::current_exe().unwrap());
+    command_obj
+        .arg("--runner-pool")
+        .arg(format!("{}:{}", daemon_pool.name, daemon_pool.size))
+        .arg("--runner-pool-dir")
+        .arg(pools_dir)
+        .arg("--runner-hold-file")
+        .arg(&hold_path)
+        .args(&sweep.args)
+        .current_dir(&sweep.cwd)
+        .env_clear()
+        .envs(sweep.env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(log_file.try_clone().unwrap()))
+        .stderr(Stdio::from(log_file));
+    sys::set_process_group(&mut command_obj);
+    match command_obj.spawn() {
+        Ok(child) => {
+            println!("Sweep {} started: {}", sweep.id, log_path.display());
+            sweep.child = Some(child);
+        },
+        Err(e) => {
+            println!("Error: couldn't start the sweep {}: {}", sweep.id, e);
+            sweep.end_state = Some(format!("failed ({})", e));
+        }
+    }
+}
+
+
+/// Handle a request of a client, and send the response.
+fn handle_request(mut stream: UnixStream, sweeps: &mut Vec<Sweep>, logs_dir: &Path) {
+    // The accepted stream may inherit the non-blocking mode of the listener.
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut request = String::new();
+    if stream.read_to_string(&mut request).is_err() {
+        return;
+    }
+    let fields: Vec<&str> = request.split(FIELD_SEPARATOR).collect();
+    let response = match fields[0] {
+        "submit" => submit_request(&fields, sweeps),
+        "queue" => queue_request(sweeps),
+        "cancel" | "logs" => match find_sweep(sweeps, fields.get(1).copied().unwrap_or("")) {
+            Ok(sweep) if fields[0] == "cancel" => cancel_request(sweep),
+            Ok(sweep) => match fs::read_to_string(logs_dir.join(format!("{}.log", sweep.id))) {
+                Ok(log) => log,
+                Err(_) => format!("Sweep {} has no logs yet.\n", sweep.id),
+            },
+            Err(e) => e,
+        },
+        _ => "Error: unknown request.\n".to_string(),
+    };
+    let _ = stream.write_all(response.as_bytes());
+}
+
+
+/// Find the sweep with the given id.
+fn find_sweep<'a>(sweeps: &'a mut [Sweep], id: &str) -> Result<&'a mut Sweep, String> {
+    let sweep = id.parse::<usize>().ok()
+        .and_then(|id| sweeps.iter_mut().find(|x| x.id == id));
+    sweep.ok_or(format!("Error: no sweep with id {}.\n", id))
+}
+
+
+/// Queue a submitted sweep: priority, directory, environment and arguments.
+fn submit_request(fields: &[&str], sweeps: &mut Vec<Sweep>) -> String {
+    let priority = fields.get(1).and_then(|x| x.parse::<i32>().ok());
+    let env_len = fields.get(3).and_then(|x| x.parse::<usize>().ok());
+    let (priority, cwd, env_len) = match (priority, fields.get(2), env_len) {
+        (Some(priority), Some(cwd), Some(env_len)) if fields.len() > 4 + env_len => (priority, cwd, env_len),
+        _ => return "Error: invalid submit request.\n".to_string(),
+    };
+    let env = fields[4..4 + env_len].iter()
+        .filter_map(|x| x.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let sweep = Sweep {
+        id: sweeps.len() + 1,
+        priority,
+        cwd: cwd.to_string(),
+        env,
+        args: fields[4 + env_len..].iter().map(|x| x.to_string()).collect(),
+        child: None,
+        cancelled: false,
+        end_state: None,
+    };
+    println!("Sweep {} submitted: $ runner {}", sweep.id, shell::quote_command(&sweep.args));
+    let response = format!("Sweep {} submitted.\n", sweep.id);
+    sweeps.push(sweep);
+    response
+}
+
+
+/// List the sweeps, with their state.
+fn queue_request(sweeps: &[Sweep]) -> String {
+    let mut response = format!("{:<4} {:<8} {:<20} {}\n", "ID", "PRIORITY", "STATE", "SWEEP");
+    for sweep in sweeps {
+        response.push_str(&format!(
+            "{:<4} {:<8} {:<20} cd {} && runner {}\n",
+            sweep.id,
+            sweep.priority,
+            sweep.get_state(),
+            shell::shell_quote(&sweep.cwd),
+            shell::quote_command(&sweep.args),
+        ));
+    }
+    response
+}
+
+
+/// Cancel a queued sweep, or terminate a running one with its commands.
+fn cancel_request(sweep: &mut Sweep) -> String {
+    if sweep.end_state.is_some() {
+        return format!("Error: sweep {} is already {}.\n", sweep.id, sweep.get_state());
+    }
+    sweep.cancelled = true;
+    // Running sweeps are reported as cancelled when they exit.
+    match &mut sweep.child {
+        Some(child) => sys::signal_process_group(child, sys::SIGTERM),
+        None => {
+            sweep.end_state = Some("cancelled".to_string());
+            println!("Sweep {} cancelled.", sweep.id);
+        },
+    }
+    format!("Sweep {} cancelled.\n", sweep.id)
+}
@@ -0,0 +1,154 @@
+use crate::{Run, FLAG_VALUE, SEPARATOR};
+use crate::shell::{quote_command, shell_quote};
+use crate::table::{COMMAND_COLUMN, REPLICA_COLUMN};
+
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+
+fn json_array(strings: &[String]) -> String {
+    let strings: Vec<String> = strings.iter().map(|x| json_string(x)).collect();
+    format!("[{}]", strings.join(", "))
+}
+
+
+/// Get the fields of the JSON object of a run.
+fn json_run_fields(run: &Run) -> Vec<String> {
+    let env: Vec<String> = run.env.iter()
+        .map(|(key, value)| format!("{}: {}", json_string(key), json_string(value)))
+        .collect();
+    let mut options = Vec::new();
+    for (key, value) in &run.combination {
+        let json_value = if value.is_empty() {
+            "true".to_string()
+        } else if value.contains(SEPARATOR) {
+            let values: Vec<String> = value.split(SEPARATOR).map(String::from).collect();
+            json_array(&values)
+        } else {
+            json_string(value)
+        };
+        options.push(format!("{}: {}", json_string(key), json_value));
+    }
+    let mut fields = vec![
+        format!("\"command\": {}", run.command_idx),
+        format!("\"replica\": {}", run.replica),
+        format!("\"argv\": {}", json_array(&run.argv)),
+        format!("\"env\": {{{}}}", env.join(", ")),
+        format!("\"options\": {{{}}}", options.join(", ")),
+    ];
+    if let Some(workdir) = &run.workdir {
+        fields.push(format!("\"workdir\": {}", json_string(workdir)));
+    }
+    fields
+}
+
+
+/// Export the runs as a JSON array with the arguments, the environment
+/// variables and the option values of each run. Flags have the value `true`
+/// and options with several values are arrays.
+pub(crate) fn to_json(runs: &[Run]) -> String {
+    let json_runs: Vec<String> = runs.iter()
+        .map(|run| format!("  {{{}}}", json_run_fields(run).join(", ")))
+        .collect();
+    format!("[\n{}\n]\n", json_runs.join(",\n"))
+}
+
+
+/// Describe a run (`run.json`) with its start and end times and its exit
+/// status, which are `null` while it is running. The exit status is also
+/// `null` if the command was terminated by a signal.
+pub(crate) fn to_run_json(
+    run: &Run,
+    start_time: &str,
+    end_time: Option<&str>,
+    exit_status: Option<i32>,
+) -> String {
+    let mut fields = json_run_fields(run);
+    fields.push(format!("\"start_time\": {}", json_string(start_time)));
+    fields.push(format!("\"end_time\": {}", end_time.map_or("null".to_string(), json_string)));
+    fields.push(format!("\"exit_status\": {}", exit_status.map_or("null".to_string(), |x| x.to_string())));
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
+
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+
+/// Export the runs as CSV, with one column per option, which can be read with
+/// `--runner-table`. Empty cells mean that the option is omitted, flags have
+/// the value `[FLAG!]`, and options with several values are separated by
+/// `[BREAK!]`, so that they are passed as several arguments again.
+pub(crate) fn to_csv(runs: &[Run]) -> String {
+    let mut options: Vec<&String> = Vec::new();
+    for run in runs {
+        for (key, _) in &run.combination {
+            if !options.contains(&key) {
+                options.push(key);
+            }
+        }
+    }
+    let mut header = vec![COMMAND_COLUMN.to_string(), REPLICA_COLUMN.to_string()];
+    header.extend(options.iter().map(|x| csv_field(x)));
+    let mut lines = vec![header.join(",")];
+    for run in runs {
+        let mut fields = vec![run.command_idx.to_string(), run.replica.to_string()];
+        for option in &options {
+            let value = run.combination.iter()
+                .find(|(key, _)| &key == option)
+                .map(|(_, value)| if value.is_empty() {
+                    FLAG_VALUE.to_string()
+                } else {
+                    value.clone()
+                })
+                .unwrap_or_default();
+            fields.push(csv_field(&value));
+        }
+        lines.push(fields.join(","));
+    }
+    lines.join("\n") + "\n"
+}
+
+
+/// Export the runs as a POSIX shell script, with one quoted command per run.
+/// Runs with a working directory are run in a subshell that creates it.
+pub(crate) fn to_shell_script(runs: &[Run]) -> String {
+    let mut script = String::from("#!/bin/sh\n\n");
+    for run in runs {
+        let mut words: Vec<String> = run.env.iter()
+            .map(|(key, value)| format!("{}={}", key, shell_quote(value)))
+            .collect();
+        words.push(quote_command(&run.argv));
+        match &run.workdir {
+            Some(workdir) => {
+                let workdir = shell_quote(workdir);
+                script.push_str(&format!(
+                    "(mkdir -p {} && cd {} && {})", workdir, workdir, words.join(" ")
+                ));
+            },
+            None => script.push_str(&words.join(" ")),
+        }
+        script.push('\n');
+    }
+    script
+}
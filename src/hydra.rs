@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::exit;
+
+use crate::Grid;
+
+
+/// Split the string by the separator, ignoring the separators that are inside
+/// brackets, parentheses or quotes.
+fn split_top_level(string: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in string.chars() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+            },
+            None => {
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                } else if c == '[' || c == '(' || c == '{' {
+                    depth += 1;
+                } else if c == ']' || c == ')' || c == '}' {
+                    depth -= 1;
+                } else if c == separator && depth == 0 {
+                    parts.push(current.trim().to_string());
+                    current = String::new();
+                    continue;
+                }
+            },
+        }
+        current.push(c);
+    }
+    parts.push(current.trim().to_string());
+    parts
+}
+
+
+/// Get the arguments of a sweep function call (e.g. `range(1,5)`), if the
+/// value is a call to the given function.
+fn get_function_args(value: &str, function: &str) -> Option<Vec<String>> {
+    let args = value.strip_prefix(function)?
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    Some(split_top_level(args, ','))
+}
+
+
+fn parse_number(override_arg: &str, number: &str) -> f64 {
+    match number.parse() {
+        Ok(n) => n,
+        Err(_) => {
+            println!("Error: invalid number '{}' in override: {}", number, override_arg);
+            exit(1);
+        }
+    }
+}
+
+
+/// Hydra range: like Python's range, but it also supports floats.
+fn get_range_values(override_arg: &str, range_args: &[String]) -> Vec<String> {
+    let numbers: Vec<f64> = range_args.iter()
+        .map(|x| parse_number(override_arg, x))
+        .collect();
+    let (start, stop, step) = match numbers.as_slice() {
+        [stop] => (0.0, *stop, 1.0),
+        [start, stop] => (*start, *stop, 1.0),
+        [start, stop, step] if *step != 0.0 => (*start, *stop, *step),
+        _ => {
+            println!("Error: invalid range in override: {}", override_arg);
+            exit(1);
+        }
+    };
+    // Number of decimals of the values, to avoid floating point artifacts.
+    let decimals = range_args.iter()
+        .map(|x| x.split_once('.').map_or(0, |(_, d)| d.len()))
+        .max()
+        .unwrap_or(0);
+    let mut values = Vec::new();
+    let mut i = 0;
+    loop {
+        // The values are compared after rounding, so that the stop is excluded
+        // even if the value is slightly lower (e.g. 0.3 * 3 = 0.8999...).
+        let value = format!("{:.*}", decimals, start + i as f64 * step);
+        let rounded_value: f64 = value.parse().unwrap();
+        if (step > 0.0 && rounded_value >= stop) || (step < 0.0 && rounded_value <= stop) {
+            break;
+        }
+        values.push(value);
+        i += 1;
+    }
+    values
+}
+
+
+/// Check if the text matches the shell-style pattern (with `*` and `?`).
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last star in the pattern and the matched text position.
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+
+fn get_patterns(patterns: &str) -> Vec<String> {
+    match patterns.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        Some(patterns) => split_top_level(patterns, ','),
+        None => vec![patterns.to_string()],
+    }
+}
+
+
+/// Hydra glob: select the options of a config group (i.e. the config files in
+/// the group directory) that match the patterns.
+fn get_glob_values(
+    override_arg: &str,
+    key: &str,
+    glob_args: &[String],
+    config_dir: &str,
+) -> Vec<String> {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for glob_arg in glob_args {
+        if let Some(patterns) = glob_arg.strip_prefix("exclude=") {
+            exclude.append(&mut get_patterns(patterns));
+        } else if let Some(patterns) = glob_arg.strip_prefix("include=") {
+            include.append(&mut get_patterns(patterns));
+        } else {
+            include.append(&mut get_patterns(glob_arg));
+        }
+    }
+    let group = key.trim_start_matches(['+', '~']).replace('.', "/");
+    let group_dir = format!("{}/{}", config_dir, group);
+    let entries = match fs::read_dir(&group_dir) {
+        Ok(entries) => entries,
+        Err(why) => {
+            println!("Error: couldn't read config group {} of override {}: {}", group_dir, override_arg, why);
+            exit(1);
+        }
+    };
+    let mut values = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let option = match file_name.strip_suffix(".yaml").or(file_name.strip_suffix(".yml")) {
+            Some(option) => option.to_string(),
+            None => continue,
+        };
+        if include.iter().any(|x| wildcard_match(x, &option))
+            && !exclude.iter().any(|x| wildcard_match(x, &option))
+        {
+            values.push(option);
+        }
+    }
+    values.sort();
+    values
+}
+
+
+/// Translate Hydra multirun overrides (e.g. `model=vgg,resnet`,
+/// `lr=range(0.1,0.5,0.1)`, `optimizer=choice(sgd,adam)` or
+/// `model=glob(res*)`) into a grid of combinations.
+pub(crate) fn parse_hydra_grid(grid_args: &[String], config_dir: &str) -> Grid {
+    let mut multi_args = BTreeMap::new();
+    let mut joined_args = Vec::new();
+    for (i, override_arg) in grid_args.iter().enumerate() {
+        let (key, value) = match override_arg.split_once('=') {
+            Some((key, value)) => (key.to_string(), value),
+            None => {
+                // Overrides without values (e.g. ~db) are passed as flags.
+                multi_args.insert(i, (override_arg.clone(), Vec::new()));
+                continue;
+            }
+        };
+        let values = if let Some(range_args) = get_function_args(value, "range") {
+            get_range_values(override_arg, &range_args)
+        } else if let Some(choice_args) = get_function_args(value, "choice") {
+            choice_args
+        } else if let Some(glob_args) = get_function_args(value, "glob") {
+            get_glob_values(override_arg, &key, &glob_args, config_dir)
+        } else {
+            split_top_level(value, ',')
+        };
+        if values.is_empty() {
+            println!("Error: override without values: {}", override_arg);
+            exit(1);
+        }
+        joined_args.push(key.clone());
+        multi_args.insert(i, (key, values));
+    }
+    Grid {
+        multi_args,
+        positional_args: BTreeMap::new(),
+        command_specific_args: Vec::new(),
+        distributed_args: Vec::new(),
+        joined_args,
+        placeholder_args: Vec::new(),
+        conditional_args: BTreeMap::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(args: &[&str]) -> Vec<String> {
+        let range_args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+        get_range_values("x=range(...)", &range_args)
+    }
+
+    #[test]
+    fn integer_ranges() {
+        assert_eq!(range(&["3"]), ["0", "1", "2"]);
+        assert_eq!(range(&["2", "5"]), ["2", "3", "4"]);
+        assert_eq!(range(&["1", "10", "4"]), ["1", "5", "9"]);
+        assert_eq!(range(&["5", "1", "-2"]), ["5", "3"]);
+        assert!(range(&["5", "1"]).is_empty());
+    }
+
+    #[test]
+    fn float_ranges() {
+        assert_eq!(range(&["0.1", "0.5", "0.1"]), ["0.1", "0.2", "0.3", "0.4"]);
+        // 0.3 * 3 is 0.8999..., which is the stop when rounded.
+        assert_eq!(range(&["0", "0.9", "0.3"]), ["0.0", "0.3", "0.6"]);
+        assert_eq!(range(&["1", "0", "-0.25"]), ["1.00", "0.75", "0.50", "0.25"]);
+        assert_eq!(range(&["0", "1.5"]), ["0.0", "1.0"]);
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("res*", "resnet"));
+        assert!(wildcard_match("*net", "resnet"));
+        assert!(wildcard_match("r*n*t", "resnet"));
+        assert!(wildcard_match("vgg1?", "vgg16"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("vgg1?", "vgg1"));
+        assert!(!wildcard_match("res*", "vgg"));
+        assert!(!wildcard_match("*net", "resnets"));
+        assert!(!wildcard_match("", "a"));
+    }
+
+    #[test]
+    fn top_level_split() {
+        assert_eq!(split_top_level("a, range(1,2), [b,c], 'd,e'", ','), ["a", "range(1,2)", "[b,c]", "'d,e'"]);
+    }
+}
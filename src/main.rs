@@ -1,27 +1,181 @@
 use std::env;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::{ErrorKind, BufReader, BufRead, Write};
-use std::process::{Command, Stdio, Child, exit};
-use std::fs::{File, OpenOptions};
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio, Child, ExitStatus, exit};
+use std::fs::{self, File, OpenOptions};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 mod time_utils;
+mod hydra;
+mod parallel;
+mod table;
+mod export;
+mod shell;
+mod sys;
+mod pool;
+#[cfg(unix)]
+mod daemon;
+
+
+const SEPARATOR: &str = "[BREAK!]";
+// Value used for options that are present but do not take any value.
+const FLAG_VALUE: &str = "[FLAG!]";
+// Value used for toggleable flags that are absent.
+const NO_FLAG_VALUE: &str = "[NO_FLAG!]";
+// Arguments that separate the grids whose combinations are joined.
+const GRID_SEPARATOR: [&str; 3] = ["--", "+", "--"];
+// Runner options that can be scoped to some commands (e.g. --1,filter-runs).
+const SCOPED_OPTIONS: [&str; 5] = [
+    "filter-runs", "allow-runs", "runner-env", "runner-timeout", "runner-resources"
+];
+// Slot pool of the CPUs of the runs, which is also their environment variable.
+const CPU_SLOT_POOL: &str = "RUNNER_CPUS";
+// Environment variables of the number of threads of common libraries.
+const THREADS_ENV_VARS: [&str; 2] = ["OMP_NUM_THREADS", "MKL_NUM_THREADS"];
+// Time given to the commands to exit after being terminated, before they are
+// killed.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 
 const HELP: &str = "\
 Usage: runner [option] <command> [--] <args>
+       runner daemon [--runners <n>] [--runner-daemon-dir <dir>]
+       runner submit [--runner-priority <n>] [--runner-daemon-dir <dir>]
+                     [option] <command> [--] <args>
+       runner queue [--runner-daemon-dir <dir>]
+       runner cancel|logs [--runner-daemon-dir <dir>] <id>
 Options:
     --allow-runs <combs>
                         Allow only certain combinations of arguments.
+    --<commands>,allow-runs, --<commands>,filter-runs,
+    --<commands>,runner-env, --<commands>,runner-timeout
+                        Scope the option to some commands (e.g., --1,
+                          --0+2, --!0 or --eval).
     --bg-runner         Run the commands in the background.
     --dry-runner        Print all the commands that would be executed without
                           actually executing them.
     --filter-runs <combs>
                         Filter certain combinations of arguments.
     -h, --help          Print this help message.
-    --runners           Number of commands to run in parallel.
+    --runner-export <format>
+                        Export the planned runs as json, csv or sh (shell
+                          script) and exit.
+    --runner-export-file <file>
+                        File of the exported runs (default:
+                          runner_plan.<format>).
+    --runner-hydra      Parse the arguments as Hydra multirun overrides
+                          (e.g., model=vgg,resnet 'lr=range(0.1,0.5,0.1)').
+    --runner-hydra-config-dir <dir>
+                        Hydra config directory used by glob() overrides
+                          (default: conf).
+    --runners <n>       Number of commands to run in parallel, in total or
+                          per command (e.g., 4, 0:2,1:8 or train:2,eval:8).
+    --runner-env <name>=<value>
+                        Set an environment variable for the commands (can
+                          be repeated).
+    --runner-capacity <resources>
+                        Resources of the machine (e.g., cpus=16,mem=64G,gpus=2),
+                          so that the runs in parallel never request more.
+    --runner-resources <resources>
+                        Resources requested by each run, which can contain
+                          placeholders (e.g., cpus=4,mem={batch-size}M).
+    --runner-slots <name>=<slots>
+                        Give each running command a slot of the pool, as an
+                          environment variable and placeholder (e.g.,
+                          CUDA_VISIBLE_DEVICES=0,1,2,3 or
+                          CUDA_VISIBLE_DEVICES=0,1|2,3; can be repeated).
+    --runner-cpus-per-run <n>
+                        Pin each running command to n CPUs, partitioning
+                          the CPUs that runner can run on.
+    --runner-cpus <cpus>
+                        Pin each running command to one of the groups of
+                          CPUs (e.g., 0-3|4-7 or 0,2|1,3).
+    --runner-set-threads
+                        Set OMP_NUM_THREADS and MKL_NUM_THREADS to the
+                          number of CPUs of each command.
+    --runner-limit <limits>
+                        Limit the resources of each command (e.g.,
+                          mem=8G,cpu-time=2h,nofile=4096; limits: mem,
+                          cpu-time, nofile, nproc, fsize, data, stack, core).
+    --runner-nice <n>   Run the commands with the given niceness.
+    --runner-ionice <class>[:<level>]
+                        Run the commands with the given I/O scheduling class
+                          (realtime, best-effort or idle) and level (0-7).
+    --runner-max-load <load>
+                        Hold the launches while the load average of the
+                          last minute exceeds the load.
+    --runner-min-free-mem <memory>
+                        Hold the launches while the available memory is
+                          less than the amount or percentage (e.g., 8G or
+                          20%).
+    --runner-stagger <duration>
+                        Minimum delay between launches (e.g., 10s).
+    --runner-control-file <file>
+                        File with the number of runners, which can be edited
+                          to change it while running (SIGUSR1 and SIGUSR2
+                          also increment and decrement it; 0 pauses the
+                          launches).
+    --runner-hold-file <file>
+                        File to which the reason why the launches are held
+                          is written (empty if they are not held).
+    --runner-pool <name>:<size>
+                        Share a pool of slots with other runner processes,
+                          so that there are no more commands running in
+                          total than the size (e.g., gpu-box:6; can be
+                          repeated).
+    --runner-pool-dir <dir>
+                        Directory of the lock files of the shared pools
+                          (default: runner-pools in the temporary
+                          directory).
+    --runner-timeout <duration>
+                        Terminate the commands that run longer than the
+                          duration (e.g., 90, 30m or 2h).
     --runner-info       Print information about the runner execution (e.g.,
                           the commands that are being executed) and exit.
+    --runner-option-format <format>
+                        Pass each option and its value as a single argument
+                          (e.g., '{option}={value}', '{name}={value}' or
+                          '-D{name}={value}').
+    --runner-repeat <n> Run each combination n times (replicas).
+    --runner-table <file>
+                        Read the combinations from a CSV (one column per
+                          option) or JSONL (one object per run) file.
+    --runner-shell [sh|bash]
+                        Run each command as a shell script (e.g., with
+                          pipelines), inserting the quoted arguments in the
+                          {args} placeholder or at the end (default: sh).
+    --runner-seed-option <option>
+                        Option used to pass the replica index to the
+                          commands (e.g., --seed).
+    --runner-sweep <name>
+                        Write the plan, the journal, the logs of the runs
+                          and the failed commands in a new directory,
+                          outputs/<date>/<time>_<name>.
+    --runner-after <command>:<commands>
+                        Run each combination of a command after the same
+                          combination of previous commands succeeds (e.g.,
+                          1:0 or 2:0+1; can be repeated).
+    --runner-creates <template>
+                        Skip the runs whose output file (e.g.,
+                          data/{size}.npy) exists and is not older than
+                          their inputs.
+    --runner-depends <template>
+                        Input file of each run for --runner-creates (can be
+                          repeated).
+    --runner-force      Run all the commands, even if their outputs are up
+                          to date.
+    --runner-workdir <template>
+                        Run each command in a fresh working directory
+                          (e.g., runs/{run_id}), with a run.json file
+                          describing the run.
+    --runner-workdir-copy <file>
+                        Copy the file into each working directory (can be
+                          repeated).
+    --runner-workdir-link <file>
+                        Link the file into each working directory (can be
+                          repeated).
     -v, --version       Print the version of runner.
 
 Exit status:
@@ -40,44 +194,454 @@ fn print_version() {
 }
 
 
-fn wait_for_child(child: &mut Child) -> bool {
-    // If the return value of the prgram is different than 0, then return false,
-    //  otherwise return true.
+fn wait_for_child(child: &mut Child) -> Option<ExitStatus> {
+    // Return the exit status of the program, or None if it could not be
+    //  waited for.
     match child.try_wait() {
         Ok(Some(status)) => {
             println!("exited with: {status}");
-            status.success()
+            Some(status)
         },
         Ok(None) => {
             let res = child.wait();
-            res.unwrap().success()
+            Some(res.unwrap())
         }
         Err(e) => {
             println!("error attempting to wait: {e}");
-            false
+            None
         }
     }
 }
 
 
+/// Print the command quoted for POSIX shells, and return it. Commands with a
+/// working directory are preceded by `cd <dir> &&`.
 fn print_command(command_obj: &Command) -> String {
-    let mut command_string = String::new();
     println!("{}", "-".repeat(80));
-    let command_name = command_obj.get_program().to_str().unwrap();
-    print!("$ {} ", command_name);
-    command_string.push_str(command_name);
-    command_string.push(' ');
-    for arg in command_obj.get_args() {
-        let arg_str = arg.to_str().unwrap();
-        print!("{} ", arg_str);
-        command_string.push_str(arg_str);
-        command_string.push(' ');
+    let mut argv = vec![command_obj.get_program()];
+    argv.extend(command_obj.get_args());
+    let mut command_string = shell::quote_command(&argv);
+    if let Some(dir) = command_obj.get_current_dir() {
+        command_string = format!(
+            "cd {} && {}", shell::shell_quote_os(dir.as_os_str()), command_string
+        );
     }
-    println!();
+    println!("$ {}", command_string);
     command_string
 }
 
 
+/// Create the working directory of a run, with the input files copied or
+/// linked into it.
+fn create_workdir(workdir: &str, copy_files: &[String], link_files: &[String]) {
+    if let Err(why) = fs::create_dir_all(workdir) {
+        println!("Error: couldn't create the working directory {}: {}", workdir, why);
+        exit(1);
+    }
+    for (file, copy) in copy_files.iter().map(|x| (x, true))
+        .chain(link_files.iter().map(|x| (x, false)))
+    {
+        let file_name = match Path::new(file).file_name() {
+            Some(file_name) => file_name,
+            None => {
+                println!("Error: invalid input file: {}", file);
+                exit(1);
+            }
+        };
+        let target = Path::new(workdir).join(file_name);
+        // Input files that are already in the working directory are kept.
+        if target.symlink_metadata().is_ok() {
+            continue;
+        }
+        let result = if copy {
+            fs::copy(file, &target).map(|_| ())
+        } else {
+            // Links are absolute, so that they do not depend on the working
+            // directory.
+            fs::canonicalize(file).and_then(|source| {
+                #[cfg(unix)]
+                return std::os::unix::fs::symlink(source, &target);
+                #[cfg(not(unix))]
+                return fs::copy(source, &target).map(|_| ());
+            })
+        };
+        if let Err(why) = result {
+            println!("Error: couldn't copy or link {} into {}: {}", file, workdir, why);
+            exit(1);
+        }
+    }
+}
+
+
+/// Check if the output of the run exists and is not older than its inputs,
+/// like the targets of make. Missing inputs are ignored.
+fn is_up_to_date(run: &Run) -> bool {
+    let creates = match &run.creates {
+        Some(creates) => creates,
+        None => return false,
+    };
+    let creates_time = match fs::metadata(creates).and_then(|x| x.modified()) {
+        Ok(creates_time) => creates_time,
+        Err(_) => return false,
+    };
+    run.depends.iter().all(|depends| {
+        fs::metadata(depends)
+            .and_then(|x| x.modified())
+            .map_or(true, |depends_time| depends_time <= creates_time)
+    })
+}
+
+
+/// Check if the combinations have the same values in their common options.
+fn is_same_combination(a: &[(String, String)], b: &[(String, String)]) -> bool {
+    a.iter().all(|(key, value)| {
+        b.iter().all(|(other_key, other_value)| key != other_key || value == other_value)
+    })
+}
+
+
+/// Parse a dependency between commands (e.g. `2:0+1`, i.e., command 2 runs
+/// after commands 0 and 1), and add it to the dependencies.
+fn parse_after(arg: &str, after_commands: &mut BTreeMap<usize, Vec<usize>>) {
+    let parse_index = |x: &str| match x.trim().parse::<usize>() {
+        Ok(index) => index,
+        Err(_) => {
+            println!("Error: invalid command index '{}' in --runner-after {}.", x, arg);
+            exit(1);
+        }
+    };
+    let (command, dependencies) = match arg.split_once(':') {
+        Some((command, dependencies)) => (command, dependencies),
+        None => {
+            println!("Error: --runner-after requires <command>:<commands> (e.g. 1:0), got {}.", arg);
+            exit(1);
+        }
+    };
+    let command = parse_index(command);
+    for dependency in dependencies.split('+') {
+        let dependency = parse_index(dependency);
+        // Commands can only depend on previous commands, which avoids cycles.
+        if dependency >= command {
+            println!("Error: command {} can only run after previous commands, not {}.", command, dependency);
+            exit(1);
+        }
+        after_commands.entry(command).or_default().push(dependency);
+    }
+}
+
+
+/// Print the skipped run, whose output is up to date.
+fn print_skipped_run(run: &Run, sweep_dir: Option<&Path>, run_idx: usize) {
+    println!("{}", "-".repeat(80));
+    println!("Skipped ({} is up to date): $ {}", run.creates.as_ref().unwrap(), shell::quote_command(&run.argv));
+    write_journal(sweep_dir, &format!("skip {}", run_idx));
+}
+
+
+/// Append a line to the journal of the sweep, prefixed by the current time.
+fn write_journal(sweep_dir: Option<&Path>, line: &str) {
+    let sweep_dir = match sweep_dir {
+        Some(sweep_dir) => sweep_dir,
+        None => return,
+    };
+    let now_str = time_utils::get_date_time_string(SystemTime::now());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(sweep_dir.join("journal.log"))
+        .unwrap();
+    file.write_all(format!("{} {}\n", now_str, line).as_bytes()).unwrap();
+}
+
+
+/// Wait for a running command to finish and record its end, returning its
+/// exit status.
+fn finish_run(
+    running_command: &mut RunningCommand,
+    runs: &[Run],
+    sweep_dir: Option<&Path>,
+) -> Option<ExitStatus> {
+    let status = wait_for_child(&mut running_command.child);
+    let run_idx = running_command.run_idx;
+    write_run_json(&runs[run_idx], running_command.start_time, Some(SystemTime::now()), status);
+    let status_string = status.map_or("unknown".to_string(), |x| x.to_string());
+    write_journal(sweep_dir, &format!("end {} {}", run_idx, status_string));
+    status
+}
+
+
+/// Parse the resource limits of the commands (e.g. `mem=8G,cpu-time=2h`).
+fn parse_limits(arg: &str) -> Vec<(i32, u64)> {
+    let mut rlimits = Vec::new();
+    for limit in arg.split(',') {
+        let (name, value) = limit.split_once('=').unwrap_or((limit, ""));
+        let resource = sys::RLIMITS.iter().find(|x| x.0 == name).map(|x| x.1);
+        // CPU time is a duration, and the rest are amounts (e.g. 8G).
+        let value = if name == "cpu-time" {
+            parse_duration(value).map(|x| x.as_secs().max(1) as f64)
+        } else {
+            parse_amount(value)
+        };
+        match (resource, value) {
+            (Some(resource), Some(value)) => rlimits.push((resource, value as u64)),
+            _ => {
+                let names: Vec<&str> = sys::RLIMITS.iter().map(|x| x.0).collect();
+                println!(
+                    "Error: invalid limit '{}' in --runner-limit (e.g., mem=8G,cpu-time=2h,nofile=4096; limits: {}).",
+                    limit, names.join(", ")
+                );
+                exit(1);
+            }
+        }
+    }
+    rlimits
+}
+
+
+/// Describe the limit that probably killed the command, given the signal that
+/// terminated it.
+fn get_limit_description(signal: i32, rlimits: &[(i32, u64)]) -> Option<&'static str> {
+    let has_limit = |name: &str| {
+        sys::RLIMITS.iter().any(|(x, resource)| x == &name && rlimits.iter().any(|y| y.0 == *resource))
+    };
+    // Only the soft limits are set, so exceeding them sends SIGXCPU or SIGXFSZ,
+    //   or makes the allocations fail, but never SIGKILL.
+    match signal {
+        sys::SIGXCPU if has_limit("cpu-time") => Some("CPU time limit, SIGXCPU"),
+        sys::SIGXFSZ if has_limit("fsize") => Some("file size limit, SIGXFSZ"),
+        // Failed memory allocations usually abort or crash the command.
+        sys::SIGABRT | sys::SIGSEGV if has_limit("mem") || has_limit("data") || has_limit("stack") => {
+            Some("memory limit, failed allocation")
+        },
+        _ => None,
+    }
+}
+
+
+/// Parse an amount of a resource, optionally with a binary unit (e.g. `4`,
+/// `0.5`, `512M` or `20G`).
+fn parse_amount(arg: &str) -> Option<f64> {
+    let (number, unit) = match arg.char_indices().last() {
+        Some((i, 'K')) => (&arg[..i], 1024.0),
+        Some((i, 'M')) => (&arg[..i], 1024.0 * 1024.0),
+        Some((i, 'G')) => (&arg[..i], 1024.0 * 1024.0 * 1024.0),
+        Some((i, 'T')) => (&arg[..i], 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        _ => (arg, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Some(number * unit),
+        _ => None,
+    }
+}
+
+
+/// Parse a list of resources and their amounts (e.g. `cpus=4,mem=20G`).
+fn parse_resources(arg: &str, option: &str) -> Vec<(String, f64)> {
+    let mut resources: Vec<(String, f64)> = Vec::new();
+    for resource in arg.split(',') {
+        let amount = resource.split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .and_then(|(name, amount)| Some((name, parse_amount(amount)?)));
+        match amount {
+            Some((name, amount)) => {
+                // Later amounts of the same resource replace the previous ones.
+                resources.retain(|x| x.0 != name);
+                resources.push((name.to_string(), amount));
+            },
+            None => {
+                println!("Error: invalid resource '{}' in {} {} (e.g., cpus=4,mem=20G).", resource, option, arg);
+                exit(1);
+            }
+        }
+    }
+    resources
+}
+
+
+/// Check if the resources requested by the run are available, given the ones
+/// requested by the running runs.
+fn fits_capacity(
+    run: &Run,
+    running_runs: &[usize],
+    runs: &[Run],
+    capacity: &[(String, f64)],
+) -> bool {
+    run.resources.iter().all(|(name, amount)| {
+        let used: f64 = running_runs.iter()
+            .flat_map(|x| &runs[*x].resources)
+            .filter(|x| &x.0 == name)
+            .map(|x| x.1)
+            .sum();
+        let available = capacity.iter().find(|x| &x.0 == name).map_or(0.0, |x| x.1);
+        used + amount <= available
+    })
+}
+
+
+/// Find the first pending run that can start: there are runners left for it
+/// (in total and for its command), its dependencies have succeeded, and its
+/// resources are available.
+fn find_ready_run(
+    pending_runs: &[usize],
+    running_runs: &[usize],
+    runs: &[Run],
+    run_states: &[RunState],
+    command_settings: &[CommandSettings],
+    runners: usize,
+    capacity: &[(String, f64)],
+) -> Option<usize> {
+    if running_runs.len() >= runners {
+        return None;
+    }
+    pending_runs.iter().position(|&run_idx| {
+        let command_idx = runs[run_idx].command_idx;
+        let command_running = running_runs.iter()
+            .filter(|x| runs[**x].command_idx == command_idx)
+            .count();
+        command_running < command_settings[command_idx].runners
+            && runs[run_idx].after.iter().all(|&x| run_states[x] == RunState::Succeeded)
+            && fits_capacity(&runs[run_idx], running_runs, runs, capacity)
+    })
+}
+
+
+/// Change the number of runners, and the number of runners of the commands
+/// that were not given their own (e.g. with `--runners 0:2`).
+fn set_runners(command_settings: &mut [CommandSettings], runners: usize) {
+    for settings in command_settings.iter_mut().filter(|x| !x.explicit_runners) {
+        settings.runners = runners;
+    }
+}
+
+
+/// Parse a slot pool (e.g. `CUDA_VISIBLE_DEVICES=0,1,2,3`, or
+/// `CUDA_VISIBLE_DEVICES=0,1|2,3` for slots with several values).
+fn parse_slot_pool(arg: &str) -> (String, Vec<String>) {
+    let (name, slots) = match arg.split_once('=') {
+        Some((name, slots)) if !name.is_empty() && !slots.is_empty() => (name, slots),
+        _ => {
+            println!("Error: --runner-slots requires a <name>=<slots> argument (e.g., CUDA_VISIBLE_DEVICES=0,1).");
+            exit(1);
+        }
+    };
+    let separator = if slots.contains('|') { '|' } else { ',' };
+    (name.to_string(), slots.split(separator).map(String::from).collect())
+}
+
+
+/// Parse a list of CPUs with ranges (e.g. `0,2,4-7`).
+fn parse_cpu_list(arg: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for item in arg.split(',') {
+        let range = match item.split_once('-') {
+            Some((start, end)) => start.parse::<usize>().and_then(|x| Ok(x..=end.parse()?)),
+            None => item.parse::<usize>().map(|x| x..=x),
+        };
+        match range {
+            Ok(range) if !range.is_empty() => cpus.extend(range),
+            _ => {
+                println!("Error: invalid CPU list: {} (e.g., 0-3|4-7).", arg);
+                exit(1);
+            }
+        }
+    }
+    cpus
+}
+
+
+/// Get the reason to hold the launches, if the load or the memory pressure of
+/// the machine exceed the thresholds.
+fn get_launch_hold_reason(max_load: Option<f64>, min_free_mem: Option<&str>) -> Option<String> {
+    if let (Some(max_load), Some(load)) = (max_load, sys::get_load_average()) {
+        if load > max_load {
+            return Some(format!("load average {:.2} > {}", load, max_load));
+        }
+    }
+    if let (Some(min_free_mem), Some((total, available))) = (min_free_mem, sys::get_memory_info()) {
+        // The free memory can be a percentage of the total memory.
+        let min_available = match min_free_mem.strip_suffix('%') {
+            Some(percentage) => percentage.parse::<f64>().unwrap_or(0.0) / 100.0 * total,
+            None => parse_amount(min_free_mem).unwrap_or(0.0),
+        };
+        if available < min_available {
+            return Some(format!(
+                "available memory {:.1}G < {}",
+                available / (1024.0 * 1024.0 * 1024.0),
+                min_free_mem
+            ));
+        }
+    }
+    None
+}
+
+
+/// Parse a duration in seconds, or with a unit (e.g. `90`, `30s`, `10m`, `2h`
+/// or `1d`).
+fn parse_duration(arg: &str) -> Option<Duration> {
+    let (number, unit_secs) = match arg.char_indices().last() {
+        Some((i, 's')) => (&arg[..i], 1.0),
+        Some((i, 'm')) => (&arg[..i], 60.0),
+        Some((i, 'h')) => (&arg[..i], 3600.0),
+        Some((i, 'd')) => (&arg[..i], 86400.0),
+        _ => (arg, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => {
+            Some(Duration::from_secs_f64(number * unit_secs))
+        },
+        _ => None,
+    }
+}
+
+
+/// Write the description of the run (`run.json`) in its working directory.
+fn write_run_json(
+    run: &Run,
+    start_time: SystemTime,
+    end_time: Option<SystemTime>,
+    exit_status: Option<ExitStatus>,
+) {
+    let workdir = match &run.workdir {
+        Some(workdir) => workdir,
+        None => return,
+    };
+    let start_time = time_utils::get_date_time_string(start_time);
+    let end_time = end_time.map(time_utils::get_date_time_string);
+    let run_json = export::to_run_json(
+        run,
+        &start_time,
+        end_time.as_deref(),
+        exit_status.and_then(|x| x.code())
+    );
+    let run_json_path = Path::new(workdir).join("run.json");
+    if let Err(why) = fs::write(&run_json_path, run_json) {
+        println!("Error: couldn't write {}: {}", run_json_path.display(), why);
+    }
+}
+
+
+/// Replace the placeholders of the argument (e.g., `{replica}`) with their
+/// values.
+fn fill_placeholders(arg: &str, placeholders: &[(String, String)]) -> String {
+    let mut filled_arg = arg.to_string();
+    for (name, value) in placeholders {
+        filled_arg = filled_arg.replace(&format!("{{{}}}", name), value);
+    }
+    filled_arg
+}
+
+
+/// Format an option and its value as a single argument (e.g., `--lr=0.1`).
+///
+/// `{option}` is replaced by the option, `{name}` by the option without the
+/// dashes, and `{value}` by the value.
+fn format_option(format: &str, option: &str, value: &str) -> String {
+    format.replace("{option}", option)
+        .replace("{name}", option.trim_start_matches('-'))
+        .replace("{value}", value)
+}
+
+
 /// Given a vector containing a partial Cartesian product, and a list of items,
 /// return a vector adding the list of items to the partial Cartesian product.
 ///
@@ -165,132 +729,179 @@ pub fn parse_rules<'a>(arg: &'a str, rules_combs: &mut Vec<Vec<&'a str>>) {
     }
 }
 
-/// Check if the given string matches the following regex: -*[0-9]+,.*
-fn get_specific_arg(arg: String) -> i32 {
-    let fail_result = -1;
-    let mut number = String::new();
-    for c in arg.chars() {
-        if !c.is_numeric() {
-            if c == ',' {
-                return match number.parse::<i32>() {
-                    Ok(n) => {
-                        n
-                    },
-                    Err(_) => fail_result,
-                };
-            } else {
-                return fail_result;
-            }
-        } else {
-            number.push(c);
-        }
+/// Read the arguments from a file, one argument per line.
+fn read_file_args(file_path: &str) -> std::io::Result<Vec<String>> {
+    let file = File::open(file_path)?;
+    let reader = BufReader::new(file);
+    let mut file_args = Vec::new();
+    for line in reader.lines() {
+        file_args.push(line?);
     }
-    fail_result
+    Ok(file_args)
 }
 
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// Check if the prefix of an option (before the first comma) selects commands
+/// (e.g. `1`, `0+2`, `1-3`, `!0` or `eval`).
+fn is_command_selector(selector: &str) -> bool {
+    !selector.is_empty()
+        && selector.chars().all(|c| c.is_ascii_alphanumeric() || "_+!-".contains(c))
+}
 
-    //// Basic standard command line options.
-    if args.len() < 2 {
-        println!("{}", HELP);
-        return;
-    }
-    if args[1] == "-v" || args[1] == "--version" {
-        print_version();
-        return;
-    }
-    else if args[1] == "-h" || args[1] == "--help" {
-        println!("{}", HELP);
-        return;
-    }
-    // Exclude the first argument, which is the name of the program.
-    let command_args = &args[1..];
 
-    //// Parse command line options of runner.
-    let mut runners = 1;
-    let mut dry_run = false;
-    let mut bg_run = false;
-    let mut new_command_args = Vec::new();
-    let mut filter_combs = Vec::new();
-    let mut filter = false;
-    let mut allow_combs = Vec::new();
-    let mut allow = false;
-    let mut parse_runners = false;
-    let mut info_only = false;
-    for arg in command_args {
-        if filter {
-            if arg.starts_with("-") {
-                filter = false;
-            } else {
-                parse_rules(arg, &mut filter_combs);
-                continue;
-            }
-        } else if allow {
-            if arg.starts_with("-") {
-                allow = false;
-            } else {
-                parse_rules(arg, &mut allow_combs);
-                continue;
-            }
-        } else if parse_runners {
-            runners = match arg.parse() {
-                Ok(n) => n,
-                Err(_) => {
-                    println!("Error: --runners requires an integer argument.");
+/// Get the indices of the commands selected by a command selector: indices,
+/// ranges of indices (e.g. `1-3`) and command names, separated by `+`, and
+/// optionally negated with a leading `!`.
+fn parse_command_selector(selector: &str, arg: &str, command_names: &[String]) -> Vec<usize> {
+    let get_index = |item: &str| {
+        let index = match item.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => match command_names.iter().position(|x| !x.is_empty() && x == item) {
+                Some(index) => index,
+                None => {
+                    println!("Error: unknown command '{}' in option {}.", item, arg);
                     exit(1);
                 }
-            };
-            if runners < 1 {
-                println!("Error: --runners requires an integer argument greater than 0.");
-                exit(1);
-            }
-            parse_runners = false;
-            continue;
+            },
+        };
+        if index >= command_names.len() {
+            println!("Error: invalid command index {} in option {}.", index, arg);
+            exit(1);
         }
-        if arg == "--dry-runner" {
-            dry_run = true;
-        } else if arg == "--runner-info" {
-            info_only = true;
-        } else if arg == "--runners" {
-            parse_runners = true;
-        } else if arg == "--bg-runner" {
-            bg_run = true;
-        } else if arg == "--filter-runs" {
-            filter = true;
-        } else if arg == "--allow-runs" {
-            allow = true;
-        } else {
-            new_command_args.push(arg.clone());
+        index
+    };
+    let (negated, selector) = match selector.strip_prefix('!') {
+        Some(selector) => (true, selector),
+        None => (false, selector),
+    };
+    let mut indices = Vec::new();
+    for item in selector.split('+') {
+        // Ranges of indices include both ends.
+        match item.split_once('-') {
+            Some((start, end)) if start.parse::<usize>().is_ok() && end.parse::<usize>().is_ok() => {
+                indices.extend(get_index(start)..=get_index(end));
+            },
+            _ => indices.push(get_index(item)),
         }
     }
-    let command_args = new_command_args;
-    let mut commands = Vec::new();
-    // The command is the string before the first argument that starts with a
-    // dash.
-    let mut i = 0;
-    let mut current_command = Vec::new();
-    while i<command_args.len() {
-        // Check if command argument is equal to "--".
-        if command_args[i] == "--" {
-            commands.push(current_command.clone());
-            i += 1;
-            break;
-        // Allow for multiple commands to be run.
-        } else if command_args[i] == "," {
-            commands.push(current_command.clone());
-            current_command = Vec::new();
-            i += 1;
-            continue;
-        }
-        current_command.push(command_args[i].clone());
-        i += 1;
+    if negated {
+        indices = (0..command_names.len()).filter(|x| !indices.contains(x)).collect();
     }
-    // The remaining arguments are the arguments for the command.
-    let mut command_args = &command_args[i..];
+    indices
+}
+
+
+/// Split the name of a command (e.g. `train=train.py`), if it has one.
+fn split_command_name(command: &mut [String]) -> String {
+    let name = match command.first().and_then(|x| x.split_once('=')) {
+        Some((name, program)) if !name.is_empty() && !program.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+        {
+            name.to_string()
+        },
+        _ => return String::new(),
+    };
+    command[0] = command[0][name.len()+1..].to_string();
+    name
+}
+
+
+/// State of a run during the execution.
+#[derive(Clone, Copy, PartialEq)]
+enum RunState {
+    Pending,
+    Running,
+    // Succeeded or skipped because its output is up to date.
+    Succeeded,
+    Failed,
+}
+
+
+/// Runner settings of a command, which can be scoped to some commands.
+struct CommandSettings<'a> {
+    // Maximum number of runs of the command in parallel.
+    runners: usize,
+    // Whether the runners were given for the command, instead of being the
+    // total number of runners.
+    explicit_runners: bool,
+    filter_combs: Vec<Vec<&'a str>>,
+    allow_combs: Vec<Vec<&'a str>>,
+    env: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    // Resources requested by each run, which can contain placeholders.
+    resources: Vec<String>,
+}
+
+
+/// A command that is running.
+struct RunningCommand {
+    child: Child,
+    // Command quoted for POSIX shells.
+    command_string: String,
+    run_idx: usize,
+    start_time: SystemTime,
+    // Time when the command was terminated because of its timeout.
+    terminated_time: Option<SystemTime>,
+    // Slot held in each slot pool.
+    slots: Vec<usize>,
+    // Locks of the slots held in the shared pools, released when dropped.
+    _pool_locks: Vec<File>,
+}
+
+
+/// A run of a command with a combination of arguments.
+struct Run {
+    // Program and arguments of the command.
+    argv: Vec<String>,
+    // Environment variables set by runner.
+    env: Vec<(String, String)>,
+    // Options and values of the run.
+    combination: Vec<(String, String)>,
+    command_idx: usize,
+    // Index of the combination of the replica.
+    group_idx: usize,
+    replica: usize,
+    // Working directory of the run, if any.
+    workdir: Option<String>,
+    // Output of the run, which is skipped if it is up to date.
+    creates: Option<String>,
+    // Inputs of the run, which the output must not be older than.
+    depends: Vec<String>,
+    // Runs that must succeed before this run starts.
+    after: Vec<usize>,
+    // Time after which the run is terminated.
+    timeout: Option<Duration>,
+    // Resources requested by the run (e.g. cpus or memory).
+    resources: Vec<(String, f64)>,
+}
+
 
-    let separator_string = "[BREAK!]".to_string();
+/// Arguments of a grid of combinations.
+struct Grid {
+    multi_args: BTreeMap<usize, (String, Vec<String>)>,
+    positional_args: BTreeMap<usize, (String, Vec<String>)>,
+    // Arguments that are specific to some commands: (argument, commands).
+    command_specific_args: Vec<(String, Vec<usize>)>,
+    distributed_args: Vec<String>,
+    // Arguments whose values are joined to the option (e.g. --lr=0.1).
+    joined_args: Vec<String>,
+    // Arguments that are only inserted in the commands through placeholders.
+    placeholder_args: Vec<String>,
+    // Conditional arguments: (parent option, parent values).
+    conditional_args: BTreeMap<usize, (String, Vec<String>)>,
+}
+
+
+/// Parse the arguments of a grid of combinations (i.e., the arguments after
+/// `--` or after a grid separator).
+fn parse_grid(
+    grid_args: &[String],
+    runners: usize,
+    grid_idx: usize,
+    command_names: &[String],
+) -> Grid {
+    let mut new_command_args;
+    let mut command_args = grid_args;
     let mut multi_args = BTreeMap::new();
     let mut i = 0;
     let empty_string = "".to_string();
@@ -298,20 +909,37 @@ fn main() {
     let mut positional_size = 0;
     let mut command_specific_args = Vec::new();
     let mut distributed_args = Vec::new();
-    let mut new_command_args;
+    let mut joined_args = Vec::new();
+    let mut conditional_args = BTreeMap::new();
     let mut use_temp_files = false;
+    // Each grid uses its own directory for the distributed temporary files.
+    let temp_dir_name = match grid_idx {
+        0 => "distributed".to_string(),
+        _ => format!("distributed_{}", grid_idx),
+    };
     loop {
         if i >= command_args.len() {
             break;
         }
         if command_args[i].starts_with("-") {
-            let allowed_option_chars = "@%:,0123456789".to_string();
+            let allowed_option_chars = "@%:?=,0123456789".to_string();
             let mut arg = command_args[i].to_string();
             let mut is_distributed = false;
             let mut option_options: String = "".to_string();
+            let mut specific_commands_arg = None;
             let mut dash_start = "".to_string();
             let mut real_option_start_idx = 0;
 
+            // Command-specific argument (e.g. --1,option, --0+2,option,
+            //   --!0,option or --eval,option).
+            let undashed_arg = arg.trim_start_matches('-');
+            if let Some((selector, option)) = undashed_arg.split_once(',') {
+                if is_command_selector(selector) {
+                    let specific_commands = parse_command_selector(selector, &arg, command_names);
+                    arg = format!("{}{}", &arg[..arg.len()-undashed_arg.len()], option);
+                    specific_commands_arg = Some(specific_commands);
+                }
+            }
             //// Parse runner options for the option and get the clean option.
             for c in arg.chars() {
                 if c == '-' {
@@ -330,20 +958,43 @@ fn main() {
             // Concatenate dash_start and the arg after real_option_start_idx.
             arg = format!("{}{}", dash_start, &arg[real_option_start_idx..]);
 
+            // Conditional argument (e.g. --momentum[optimizer=sgd]): it is
+            //   only combined with the given values of the parent option.
+            if arg.ends_with(']') {
+                if let Some(start) = arg.find('[') {
+                    let condition = arg[start+1..arg.len()-1].to_string();
+                    let (parent, parent_values) = match condition.split_once('=') {
+                        Some((parent, values)) if !parent.is_empty() && !values.is_empty() => {
+                            (parent, values)
+                        },
+                        _ => {
+                            println!("Error: invalid condition: {}", arg);
+                            exit(1);
+                        }
+                    };
+                    let parent = parent.trim_start_matches('-').to_string();
+                    let parent_values: Vec<String> = parent_values.split('+')
+                        .map(|x| x.to_string())
+                        .collect();
+                    arg = arg[..start].to_string();
+                    conditional_args.insert(i, (parent, parent_values));
+                }
+            }
+
             if option_options.contains("%") {
                 is_distributed = true;
                 distributed_args.push(arg.to_string().clone());
             }
-            // Command specific argument.
-            // Check if the argument contains this regex: -[0-9]+,
-            let specific_arg_idx = get_specific_arg(option_options.clone());
-            if specific_arg_idx != -1 {
-                command_specific_args.push((arg.clone(), specific_arg_idx));
+            if option_options.contains("=") {
+                joined_args.push(arg.clone());
+            }
+            if let Some(specific_commands) = specific_commands_arg {
+                command_specific_args.push((arg.clone(), specific_commands));
             }
             if option_options.contains(":") {
                 use_temp_files = true;
                 let mut temp_file_path = env::temp_dir();
-                temp_file_path.push("distributed");
+                temp_file_path.push(&temp_dir_name);
                 // Empty directory if it exists.
                 if temp_file_path.exists() {
                     let _ = std::fs::remove_dir_all(&temp_file_path);
@@ -351,15 +1002,10 @@ fn main() {
             }
             if option_options.contains("@") {
                 // Read arguments from file.
-                let file = match File::open(command_args[i+1].clone()) {
+                let file_args = match read_file_args(&command_args[i+1]) {
                     Err(why) => panic!("couldn't open {}: {}", arg, why),
-                    Ok(file) => file,
+                    Ok(file_args) => file_args,
                 };
-                let reader = BufReader::new(file);
-                let mut file_args = Vec::new();
-                for line in reader.lines() {
-                    file_args.push(line.unwrap());
-                }
                 // Add read arguments to command arguments.
                 new_command_args = [
                     &command_args[..i],
@@ -369,12 +1015,32 @@ fn main() {
                 command_args = new_command_args.as_slice();
             }
             multi_args.insert(i, (arg.clone(), Vec::new()));
+            // Toggleable flag: combined both with and without the flag.
+            if option_options.contains("?") {
+                let has_values = command_args.get(i+1)
+                    .is_some_and(|x| !x.starts_with("-"));
+                if has_values {
+                    println!("Error: toggleable flags cannot take values: {}", arg);
+                    exit(1);
+                }
+                multi_args.get_mut(&i).unwrap().1.push(FLAG_VALUE.to_string());
+                multi_args.get_mut(&i).unwrap().1.push(NO_FLAG_VALUE.to_string());
+                i += 1;
+                continue;
+            }
             let initial_j = i+1;
             for (j, command_arg) in command_args.iter().enumerate().skip(initial_j) {
                 if !command_arg.starts_with("-") {
                     // if contains a comma, then split and add to positional
                     // arguments.
                     if command_arg.contains(",") {
+                        if conditional_args.contains_key(&i) {
+                            println!(
+                                "Error: conditional arguments cannot be positional: {}",
+                                arg
+                            );
+                            exit(1);
+                        }
                         positional_args.insert(i, (arg.clone(), Vec::new()));
                         let options: Vec<_> = command_arg.split(",").collect();
                         if positional_size == 0 {
@@ -396,7 +1062,7 @@ fn main() {
                         let runner_idx = (j-initial_j) % runners;
                         if use_temp_files {
                             let mut temp_file_path = env::temp_dir();
-                            temp_file_path.push("distributed");
+                            temp_file_path.push(&temp_dir_name);
                             // Create directory using temp_file_path
                             std::fs::create_dir_all(temp_file_path.clone()).unwrap();
                             temp_file_path.push(format!("{}", runner_idx));
@@ -427,7 +1093,7 @@ fn main() {
                                 *group = format!(
                                     "{}{}{}",
                                     group,
-                                    separator_string,
+                                    SEPARATOR,
                                     command_arg
                                 );
                             }
@@ -451,199 +1117,1251 @@ fn main() {
         i += 1;
     }
 
-    // Print the command that will be executed.
-    for c in &commands {
-        print!("$ ");
-        for arg in c {
-            print!("{} ", arg);
-        }
-        println!();
+    Grid {
+        multi_args,
+        positional_args,
+        command_specific_args,
+        distributed_args,
+        joined_args,
+        placeholder_args: Vec::new(),
+        conditional_args,
     }
+}
+
+
+fn print_grid(grid: &Grid) {
+    let command_specific_args = &grid.command_specific_args;
+    let distributed_args = &grid.distributed_args;
+    let conditional_args = &grid.conditional_args;
     // Pretty print multi_args.
-    for value in multi_args.values() {
+    for (key, value) in &grid.multi_args {
         let current_arg = value.0.clone();
         let specific = command_specific_args.iter().find(|x| x.0 == current_arg);
         let is_distributed = distributed_args.contains(&current_arg);
-        print!("  {}: {:?}", value.0, value.1);
-        if let Some((_, num_commas)) = specific {
-            print!(" (specific: {})", num_commas);
+        if value.1 == [FLAG_VALUE, NO_FLAG_VALUE] {
+            print!("  {}: (toggle)", value.0);
+        } else {
+            print!("  {}: {:?}", value.0, value.1);
+        }
+        if let Some((_, specific_commands)) = specific {
+            let specific_commands: Vec<String> = specific_commands.iter()
+                .map(|x| x.to_string())
+                .collect();
+            print!(" (specific: {})", specific_commands.join("+"));
         }
         if is_distributed {
             print!(" (distributed)");
         }
+        if grid.joined_args.contains(&current_arg) {
+            print!(" (joined)");
+        }
+        if grid.placeholder_args.contains(&current_arg) {
+            print!(" (placeholder)");
+        }
+        if let Some((parent, parent_values)) = conditional_args.get(key) {
+            print!(" (if {}={})", parent, parent_values.join("+"));
+        }
         println!();
     }
-    for value in positional_args.values() {
-        println!("  {}: {:?} (positional)", value.0, value.1);
+    for value in grid.positional_args.values() {
+        print!("  {}: {:?} (positional)", value.0, value.1);
+        if grid.placeholder_args.contains(&value.0) {
+            print!(" (placeholder)");
+        }
+        println!();
     }
-    println!();
+}
 
-    println!("Number of runners: {}", runners);
-    println!();
 
-    if !filter_combs.is_empty() {
-        println!("Filter runs:");
-        for comb in &filter_combs {
-            println!("  \"{}\"", comb.join(","));
+/// Check if a combination with the given option values is excluded by the
+/// filter and allow rules.
+fn is_excluded(
+    option_values: &[String],
+    filter_combs: &[Vec<&str>],
+    allow_combs: &[Vec<&str>],
+) -> bool {
+    let mut match_found = false;
+    for filter_comb in filter_combs {
+        // Check if all option values are in the filter combination.
+        match_found = filter_comb.iter()
+            .all(
+                |x| option_values.iter().any(|y| y == x)
+                );
+        if match_found {
+            break;
         }
-        println!();
     }
-    if !allow_combs.is_empty() {
-        println!("Allow runs:");
-        for comb in &allow_combs {
-            println!("  \"{}\"", comb.join(","));
+    for allow_comb in allow_combs {
+        // Check if any option values are in the allow combination.
+        // If so, then check if all option values are in the allow
+        //   combination.
+        // Check if the first allow value is in the option values.
+        //   If so, then check if all option values are in the allow
+        //   combination.
+        let first_present = option_values.iter().any(|y| y == allow_comb[0]);
+        if first_present {
+            // Check if all option values are in the allow combination.
+            match_found = !allow_comb.iter()
+                .all(
+                    |x| option_values.iter().any(|y| y == x)
+                    );
+            if match_found {
+                break;
+            }
         }
-        println!();
     }
+    match_found
+}
 
-    let mut commands_run = 0;
-    // Array of commands that are currently running.
-    let mut running_commands = Vec::new();
-    let mut failed_commands = Vec::new();
-    let mut main_command_num = 0;
-    let mut combinations_sets = BTreeMap::new();
-    let mut command_index = 0;
-    for command in &commands {
-        let mut options = Vec::new();
-        let mut flags = Vec::new();
 
-        //// Print the command that will be executed.
-        print!("$ ");
-        for arg in command {
-            print!("{} ", arg);
-        }
+/// Get the combinations of a grid for the command with index `command_num`,
+/// excluding the ones that match the filter and allow rules.
+fn get_combinations(
+    grid: &Grid,
+    command_num: usize,
+    filter_combs: &[Vec<&str>],
+    allow_combs: &[Vec<&str>],
+) -> Vec<Vec<(String, String)>> {
+    let empty_string = "".to_string();
+    let mut options = Vec::new();
+    let mut flags = Vec::new();
 
-        //// Remove all arguments from multi_args_values and
-        //// positional_args_values that are in command_specific_args and whose
-        //// number of commas is not equal to main_command_num.
-        let mut multi_args_values = Vec::new();
-        let mut positional_args_values = Vec::new();
-        for value in multi_args.values() {
-            let mut skip = false;
-            for (arg, num_commas) in &command_specific_args {
-                if &value.0 == arg {
-                    skip = num_commas != &main_command_num;
+    //// Remove all arguments from multi_args_values and
+    //// positional_args_values that are in command_specific_args and whose
+    //// commands do not include command_num.
+    let mut multi_args_values = Vec::new();
+    let mut positional_args_values = Vec::new();
+    let mut conditional_args_values = Vec::new();
+    for (key, value) in &grid.multi_args {
+        let mut skip = false;
+        for (arg, specific_commands) in &grid.command_specific_args {
+            if &value.0 == arg {
+                skip = !specific_commands.contains(&command_num);
+            }
+        }
+        if skip {
+            continue;
+        }
+        let values = &value.1.clone();
+        // copy value.1 to values
+        let mut values = values.clone();
+        if let Some((parent, parent_values)) = grid.conditional_args.get(key) {
+            // Conditional flags are combined as a single flag value.
+            if values.is_empty() {
+                values.push(FLAG_VALUE.to_string());
+            }
+            conditional_args_values.push(
+                (value.0.clone(), parent.clone(), parent_values.clone(), values)
+            );
+        } else if !values.is_empty() {
+            multi_args_values.push(values);
+            options.push(value.0.clone());
+        } else {
+            flags.push(value.0.clone());
+        }
+    }
+    for value in grid.positional_args.values() {
+        let mut skip = false;
+        for (arg, specific_commands) in &grid.command_specific_args {
+            if &value.0 == arg {
+                skip = !specific_commands.contains(&command_num);
+            }
+        }
+        if skip {
+            continue;
+        }
+        let values = &value.1.clone();
+        // copy value.1 to values
+        let values = values.clone();
+        if !values.is_empty() {
+            positional_args_values.push(values);
+            options.push(value.0.clone());
+        }
+    }
+    let mut combs;
+    combs = cartesian_product(&multi_args_values);
+    if !positional_args_values.is_empty() {
+        let positional_combs = ordered_combinations(&positional_args_values);
+        let mut new_combs = Vec::new();
+        if combs.is_empty() {
+            combs = positional_combs.clone();
+        } else {
+            for comb in &combs {
+                for positional_comb in &positional_combs {
+                    let mut new_comb = comb.clone();
+                    for value in positional_comb {
+                        new_comb.push(value.clone());
+                    }
+                    new_combs.push(new_comb);
                 }
             }
-            if skip {
+            combs = new_combs;
+        }
+    }
+    //// Expand the conditional arguments, so that their values are only
+    //// combined with the combinations that contain the parent values. The
+    //// conditional arguments whose parent is another conditional argument
+    //// are expanded after it.
+    let mut ordered_conditional_args_values = Vec::new();
+    while !conditional_args_values.is_empty() {
+        let ready_idx = conditional_args_values.iter().position(|(_, parent, _, _)| {
+            !conditional_args_values.iter().any(|x| x.0.trim_start_matches('-') == parent)
+        });
+        match ready_idx {
+            Some(ready_idx) => ordered_conditional_args_values.push(conditional_args_values.remove(ready_idx)),
+            None => {
+                let cycle_options: Vec<&str> = conditional_args_values.iter().map(|x| x.0.as_str()).collect();
+                println!("Error: cyclic conditional options: {}.", cycle_options.join(", "));
+                exit(1);
+            }
+        }
+    }
+    for (option, parent, parent_values, values) in ordered_conditional_args_values {
+        let parent_idx = options.iter()
+            .position(|x| x.trim_start_matches('-') == parent);
+        let parent_idx = match parent_idx {
+            Some(idx) => idx,
+            None => {
+                let parent_arg = grid.multi_args.values()
+                    .chain(grid.positional_args.values())
+                    .find(|x| x.0.trim_start_matches('-') == parent);
+                let parent_arg = match parent_arg {
+                    Some(parent_arg) => &parent_arg.0,
+                    None => {
+                        println!(
+                            "Error: unknown parent option '{}' for conditional option '{}'.",
+                            parent,
+                            option
+                        );
+                        exit(1);
+                    }
+                };
+                // The parent can only be missing if it is specific to
+                //   another command.
+                let parent_excluded = grid.command_specific_args.iter()
+                    .rfind(|(arg, _)| arg == parent_arg)
+                    .is_some_and(|(_, specific_commands)| !specific_commands.contains(&command_num));
+                if !parent_excluded {
+                    println!(
+                        "Error: parent option '{}' of conditional option '{}' has no values.",
+                        parent,
+                        option
+                    );
+                    exit(1);
+                }
+                options.push(option);
+                for comb in combs.iter_mut() {
+                    comb.push(empty_string.clone());
+                }
                 continue;
             }
-            let values = &value.1.clone();
-            // copy value.1 to values
-            let values = values.clone();
-            if !values.is_empty() {
-                multi_args_values.push(values);
-                options.push(value.0.clone());
+        };
+        let mut new_combs = Vec::new();
+        for comb in &combs {
+            if parent_values.contains(&comb[parent_idx]) {
+                for value in &values {
+                    let mut new_comb = comb.clone();
+                    new_comb.push(value.clone());
+                    new_combs.push(new_comb);
+                }
             } else {
-                flags.push(value.0.clone());
+                // Parent value not selected: the option is omitted.
+                let mut new_comb = comb.clone();
+                new_comb.push(empty_string.clone());
+                new_combs.push(new_comb);
             }
         }
-        for value in positional_args.values() {
-            let mut skip = false;
-            for (arg, num_commas) in &command_specific_args {
-                if &value.0 == arg {
-                    skip = num_commas != &main_command_num;
-                }
+        combs = new_combs;
+        options.push(option);
+    }
+    let mut combinations = Vec::<Vec<(String, String)>>::new();
+    for comb in combs {
+        let mut i = 0;
+        let mut option_values = Vec::new();
+        let mut this_comb = Vec::new();
+        // Get comb length.
+        for option in &options {
+            // Filter out empty argument values.
+            if comb[i].is_empty() || comb[i] == " " {
+                i += 1;
+                continue;
             }
-            if skip {
+            // Flags are referred to by their name in the rules, and absent
+            //   toggleable flags by their name preceded by '~'.
+            let flag_name = option.trim_start_matches('-');
+            if comb[i] == FLAG_VALUE {
+                this_comb.push((option.clone(), String::from("")));
+                option_values.push(flag_name.to_string());
+                i += 1;
+                continue;
+            } else if comb[i] == NO_FLAG_VALUE {
+                option_values.push(format!("~{}", flag_name));
+                i += 1;
                 continue;
             }
-            let values = &value.1.clone();
-            // copy value.1 to values
-            let values = values.clone();
-            if !values.is_empty() {
-                positional_args_values.push(values);
-                options.push(value.0.clone());
+            this_comb.push((option.clone(), comb[i].clone()));
+            // Create string with all the option values separated by a comma.
+            option_values.push(comb[i].clone());
+            i += 1;
+        }
+        for flag in &flags {
+            this_comb.push((flag.clone(), String::from("")));
+        }
+        let mut match_found = is_excluded(&option_values, filter_combs, allow_combs);
+        // Ensure that there are no duplicate combinations.
+        for combination in &combinations {
+            if combination == &this_comb {
+                match_found = true;
+                break;
             }
         }
-        main_command_num += 1;
+        if !match_found {
+            combinations.push(this_comb);
+        }
+    }
+    combinations
+}
 
-        let mut combs;
-        combs = cartesian_product(&multi_args_values);
-        if !positional_args_values.is_empty() {
-            let positional_combs = ordered_combinations(&positional_args_values);
-            let mut new_combs = Vec::new();
-            if combs.is_empty() {
-                combs = positional_combs.clone();
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    //// Basic standard command line options.
+    if args.len() < 2 {
+        println!("{}", HELP);
+        return;
+    }
+    if args[1] == "-v" || args[1] == "--version" {
+        print_version();
+        return;
+    }
+    else if args[1] == "-h" || args[1] == "--help" {
+        println!("{}", HELP);
+        return;
+    }
+    #[cfg(unix)]
+    if daemon::SUBCOMMANDS.contains(&args[1].as_str()) {
+        daemon::run_subcommand(&args[1], &args[2..]);
+        return;
+    }
+    // Exclude the first argument, which is the name of the program.
+    let command_args = &args[1..];
+
+    //// Parse command line options of runner.
+    let mut runners = 1;
+    let mut dry_run = false;
+    let mut bg_run = false;
+    let mut new_command_args = Vec::new();
+    let mut filter_combs = Vec::new();
+    let mut filter = false;
+    let mut allow_combs = Vec::new();
+    let mut allow = false;
+    let mut parse_runners = false;
+    let mut info_only = false;
+    let mut repeat = 1;
+    let mut parse_repeat = false;
+    let mut seed_option = None;
+    let mut parse_seed_option = false;
+    let mut option_format = None;
+    let mut parse_option_format = false;
+    let mut hydra = false;
+    let mut hydra_config_dir = "conf".to_string();
+    let mut parse_hydra_config_dir = false;
+    let mut table_path = None;
+    let mut parse_table_path = false;
+    let mut export_format = None;
+    let mut parse_export_format = false;
+    let mut export_file = None;
+    let mut parse_export_file = false;
+    let mut shell = None;
+    let mut parse_shell = false;
+    let mut workdir = None;
+    let mut parse_workdir = false;
+    let mut sweep_name = None;
+    let mut parse_sweep_name = false;
+    let mut creates = None;
+    let mut parse_creates = false;
+    let mut depends = Vec::new();
+    let mut parse_depends = false;
+    let mut force = false;
+    let mut after_commands = BTreeMap::new();
+    let mut parse_after_commands = false;
+    // Whether the total number of runners is given.
+    let mut total_runners = false;
+    let mut command_runners = Vec::new();
+    let mut env_vars = Vec::new();
+    let mut parse_env_var = false;
+    let mut timeouts = Vec::new();
+    let mut parse_timeout = false;
+    let mut capacity = None;
+    let mut parse_capacity = false;
+    let mut slot_pools = Vec::new();
+    let mut parse_slot_pool_arg = false;
+    let mut cpus_per_run = None;
+    let mut parse_cpus_per_run = false;
+    let mut cpu_groups = None;
+    let mut parse_cpu_groups = false;
+    let mut set_threads = false;
+    let mut max_load = None;
+    let mut parse_max_load = false;
+    let mut min_free_mem = None;
+    let mut parse_min_free_mem = false;
+    let mut stagger = None;
+    let mut parse_stagger = false;
+    let mut control_file = None;
+    let mut parse_control_file = false;
+    let mut hold_file = None;
+    let mut parse_hold_file = false;
+    let mut shared_pool_args = Vec::new();
+    let mut parse_shared_pool = false;
+    let mut pools_dir = env::temp_dir().join("runner-pools");
+    let mut parse_pools_dir = false;
+    let mut process_limits = sys::ProcessLimits { rlimits: Vec::new(), nice: None, io_priority: None };
+    let mut parse_limits_arg = false;
+    let mut limit_args = Vec::new();
+    let mut parse_nice = false;
+    let mut parse_ionice = false;
+    let mut resources = Vec::new();
+    let mut parse_resources_arg = false;
+    // Scoped options: (commands selector, value).
+    let mut scoped_filter_combs = Vec::new();
+    let mut scoped_allow_combs = Vec::new();
+    // Commands selector of the option whose values are being parsed.
+    let mut scope: Option<String> = None;
+    let mut workdir_copy_files = Vec::new();
+    let mut parse_workdir_copy_file = false;
+    let mut workdir_link_files = Vec::new();
+    let mut parse_workdir_link_file = false;
+    for arg in command_args {
+        if filter {
+            if arg.starts_with("-") {
+                filter = false;
             } else {
-                for comb in &combs {
-                    for positional_comb in &positional_combs {
-                        let mut new_comb = comb.clone();
-                        for value in positional_comb {
-                            new_comb.push(value.clone());
-                        }
-                        new_combs.push(new_comb);
+                match &scope {
+                    Some(selector) => {
+                        let mut combs = Vec::new();
+                        parse_rules(arg, &mut combs);
+                        scoped_filter_combs.extend(combs.into_iter().map(|x| (selector.clone(), x)));
+                    },
+                    None => parse_rules(arg, &mut filter_combs),
+                }
+                continue;
+            }
+        } else if allow {
+            if arg.starts_with("-") {
+                allow = false;
+            } else {
+                match &scope {
+                    Some(selector) => {
+                        let mut combs = Vec::new();
+                        parse_rules(arg, &mut combs);
+                        scoped_allow_combs.extend(combs.into_iter().map(|x| (selector.clone(), x)));
+                    },
+                    None => parse_rules(arg, &mut allow_combs),
+                }
+                continue;
+            }
+        } else if parse_runners {
+            // The runners can be given in total (e.g. 4) and per command
+            //   (e.g. 0:2,1:8 or eval:8).
+            for runners_arg in arg.split(',') {
+                let (selector, number) = match runners_arg.split_once(':') {
+                    Some((selector, number)) => (Some(selector), number),
+                    None => (None, runners_arg),
+                };
+                let number = match number.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        println!("Error: --runners requires integer arguments greater than 0 (e.g., 4 or 0:2,1:8).");
+                        exit(1);
                     }
+                };
+                match selector {
+                    Some(selector) => command_runners.push((selector.to_string(), number)),
+                    None => {
+                        runners = number;
+                        total_runners = true;
+                    },
                 }
-                combs = new_combs;
             }
-        }
-        let mut combinations = Vec::<Vec<(String, String)>>::new();
-        for comb in combs {
-            let mut i = 0;
-            let mut option_values = Vec::new();
-            let mut this_comb = Vec::new();
-            // Get comb length.
-            for option in &options {
-                // Filter out empty argument values.
-                if comb[i].is_empty() || comb[i] == " " {
-                    i += 1;
-                    continue;
+            parse_runners = false;
+            continue;
+        } else if parse_env_var {
+            match arg.split_once('=') {
+                Some((name, value)) if !name.is_empty() => {
+                    env_vars.push((scope.clone(), name.to_string(), value.to_string()));
+                },
+                _ => {
+                    println!("Error: --runner-env requires a <name>=<value> argument.");
+                    exit(1);
                 }
-                this_comb.push((option.clone(), comb[i].clone()));
-                // Create string with all the option values separated by a comma.
-                option_values.push(comb[i].as_str());
-                i += 1;
             }
-            for flag in &flags {
-                this_comb.push((flag.clone(), String::from("")));
+            parse_env_var = false;
+            continue;
+        } else if parse_max_load {
+            max_load = match arg.parse::<f64>() {
+                Ok(load) if load > 0.0 => Some(load),
+                _ => {
+                    println!("Error: --runner-max-load requires a number greater than 0.");
+                    exit(1);
+                }
+            };
+            parse_max_load = false;
+            continue;
+        } else if parse_min_free_mem {
+            let is_valid = match arg.strip_suffix('%') {
+                Some(percentage) => percentage.parse::<f64>().is_ok_and(|x| (0.0..=100.0).contains(&x)),
+                None => parse_amount(arg).is_some(),
+            };
+            if !is_valid {
+                println!("Error: --runner-min-free-mem requires an amount or a percentage (e.g., 8G or 20%).");
+                exit(1);
             }
-            let mut match_found = false;
-            for filter_comb in &filter_combs {
-                // Check if all option values are in the filter combination.
-                match_found = filter_comb.iter()
-                    .all(
-                        |x| option_values.contains(x)
-                        );
-                if match_found {
-                    break;
+            min_free_mem = Some(arg.clone());
+            parse_min_free_mem = false;
+            continue;
+        } else if parse_stagger {
+            stagger = match parse_duration(arg) {
+                Some(stagger) => Some(stagger),
+                None => {
+                    println!("Error: --runner-stagger requires a positive duration (e.g., 10s).");
+                    exit(1);
+                }
+            };
+            parse_stagger = false;
+            continue;
+        } else if parse_control_file {
+            control_file = Some(PathBuf::from(arg));
+            parse_control_file = false;
+            continue;
+        } else if parse_hold_file {
+            hold_file = Some(PathBuf::from(arg));
+            parse_hold_file = false;
+            continue;
+        } else if parse_shared_pool {
+            shared_pool_args.push(arg.clone());
+            parse_shared_pool = false;
+            continue;
+        } else if parse_pools_dir {
+            pools_dir = PathBuf::from(arg);
+            parse_pools_dir = false;
+            continue;
+        } else if parse_limits_arg {
+            process_limits.rlimits.extend(parse_limits(arg));
+            limit_args.push(arg.clone());
+            parse_limits_arg = false;
+            continue;
+        } else if parse_nice {
+            process_limits.nice = match arg.parse::<i32>() {
+                Ok(n) if (-20..=19).contains(&n) => Some(n),
+                _ => {
+                    println!("Error: --runner-nice requires an integer argument between -20 and 19.");
+                    exit(1);
+                }
+            };
+            parse_nice = false;
+            continue;
+        } else if parse_ionice {
+            let (class, level) = arg.split_once(':').unwrap_or((arg, "4"));
+            let class = sys::IO_CLASSES.iter().find(|x| x.0 == class).map(|x| x.1);
+            process_limits.io_priority = match (class, level.parse::<i32>()) {
+                (Some(class), Ok(level)) if (0..=7).contains(&level) => Some((class, level)),
+                _ => {
+                    println!("Error: --runner-ionice requires <class>[:<level>], where the class is realtime, best-effort or idle, and the level is between 0 and 7.");
+                    exit(1);
+                }
+            };
+            parse_ionice = false;
+            continue;
+        } else if parse_cpus_per_run {
+            cpus_per_run = match arg.parse::<usize>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => {
+                    println!("Error: --runner-cpus-per-run requires an integer argument greater than 0.");
+                    exit(1);
+                }
+            };
+            parse_cpus_per_run = false;
+            continue;
+        } else if parse_cpu_groups {
+            cpu_groups = Some(arg.clone());
+            parse_cpu_groups = false;
+            continue;
+        } else if parse_slot_pool_arg {
+            slot_pools.push(parse_slot_pool(arg));
+            parse_slot_pool_arg = false;
+            continue;
+        } else if parse_capacity {
+            capacity = Some(arg.clone());
+            parse_capacity = false;
+            continue;
+        } else if parse_resources_arg {
+            resources.push((scope.clone(), arg.clone()));
+            parse_resources_arg = false;
+            continue;
+        } else if parse_timeout {
+            match parse_duration(arg) {
+                Some(timeout) => timeouts.push((scope.clone(), timeout)),
+                None => {
+                    println!("Error: --runner-timeout requires a positive duration (e.g., 90, 30m or 2h).");
+                    exit(1);
                 }
             }
-            for allow_comb in &allow_combs {
-                // Check if any option values are in the allow combination.
-                // If so, then check if all option values are in the allow
-                //   combination.
-                // Check if the first allow value is in the option values.
-                //   If so, then check if all option values are in the allow
-                //   combination.
-                let first_present = option_values.contains(&allow_comb[0]);
-                if first_present {
-                    // Check if all option values are in the allow combination.
-                    match_found = !allow_comb.iter()
-                        .all(
-                            |x| option_values.contains(x)
-                            );
-                    if match_found {
-                        break;
-                    }
+            parse_timeout = false;
+            continue;
+        } else if parse_repeat {
+            repeat = match arg.parse() {
+                Ok(n) if n > 0 => n,
+                _ => {
+                    println!("Error: --runner-repeat requires an integer argument greater than 0.");
+                    exit(1);
                 }
+            };
+            parse_repeat = false;
+            continue;
+        } else if parse_seed_option {
+            seed_option = Some(arg.clone());
+            parse_seed_option = false;
+            continue;
+        } else if parse_option_format {
+            if !arg.contains("{value}") {
+                println!("Error: --runner-option-format requires a format with '{{value}}'.");
+                exit(1);
             }
-            // Ensure that there are no duplicate combinations.
-            for combination in &combinations {
-                if combination == &this_comb {
-                    match_found = true;
-                    break;
+            option_format = Some(arg.clone());
+            parse_option_format = false;
+            continue;
+        } else if parse_hydra_config_dir {
+            hydra_config_dir = arg.clone();
+            parse_hydra_config_dir = false;
+            continue;
+        } else if parse_table_path {
+            table_path = Some(arg.clone());
+            parse_table_path = false;
+            continue;
+        } else if parse_export_format {
+            if !["json", "csv", "sh"].contains(&arg.as_str()) {
+                println!("Error: --runner-export requires one of these formats: json, csv, sh.");
+                exit(1);
+            }
+            export_format = Some(arg.clone());
+            parse_export_format = false;
+            continue;
+        } else if parse_export_file {
+            export_file = Some(arg.clone());
+            parse_export_file = false;
+            continue;
+        } else if parse_after_commands {
+            parse_after(arg, &mut after_commands);
+            parse_after_commands = false;
+            continue;
+        } else if parse_creates {
+            creates = Some(arg.clone());
+            parse_creates = false;
+            continue;
+        } else if parse_depends {
+            depends.push(arg.clone());
+            parse_depends = false;
+            continue;
+        } else if parse_sweep_name {
+            sweep_name = Some(arg.clone());
+            parse_sweep_name = false;
+            continue;
+        } else if parse_workdir {
+            workdir = Some(arg.clone());
+            parse_workdir = false;
+            continue;
+        } else if parse_workdir_copy_file {
+            workdir_copy_files.push(arg.clone());
+            parse_workdir_copy_file = false;
+            continue;
+        } else if parse_workdir_link_file {
+            workdir_link_files.push(arg.clone());
+            parse_workdir_link_file = false;
+            continue;
+        } else if parse_shell {
+            // The shell is optional.
+            parse_shell = false;
+            if arg == "sh" || arg == "bash" {
+                shell = Some(arg.clone());
+                continue;
+            }
+        }
+        // Some runner options can be scoped to commands (e.g. --1,filter-runs).
+        let mut scoped_arg = arg.clone();
+        let mut arg_scope = None;
+        if let Some((selector, option)) = arg.strip_prefix("--").and_then(|x| x.split_once(',')) {
+            if is_command_selector(selector) && SCOPED_OPTIONS.contains(&option) {
+                arg_scope = Some(selector.to_string());
+                scoped_arg = format!("--{}", option);
+            }
+        }
+        let arg = &scoped_arg;
+        if arg == "--dry-runner" {
+            dry_run = true;
+        } else if arg == "--runner-info" {
+            info_only = true;
+        } else if arg == "--runners" {
+            parse_runners = true;
+        } else if arg == "--bg-runner" {
+            bg_run = true;
+        } else if arg == "--filter-runs" {
+            filter = true;
+            scope = arg_scope;
+        } else if arg == "--allow-runs" {
+            allow = true;
+            scope = arg_scope;
+        } else if arg == "--runner-env" {
+            parse_env_var = true;
+            scope = arg_scope;
+        } else if arg == "--runner-timeout" {
+            parse_timeout = true;
+            scope = arg_scope;
+        } else if arg == "--runner-max-load" {
+            parse_max_load = true;
+        } else if arg == "--runner-min-free-mem" {
+            parse_min_free_mem = true;
+        } else if arg == "--runner-stagger" {
+            parse_stagger = true;
+        } else if arg == "--runner-control-file" {
+            parse_control_file = true;
+        } else if arg == "--runner-hold-file" {
+            parse_hold_file = true;
+        } else if arg == "--runner-pool" {
+            parse_shared_pool = true;
+        } else if arg == "--runner-pool-dir" {
+            parse_pools_dir = true;
+        } else if arg == "--runner-limit" {
+            parse_limits_arg = true;
+        } else if arg == "--runner-nice" {
+            parse_nice = true;
+        } else if arg == "--runner-ionice" {
+            parse_ionice = true;
+        } else if arg == "--runner-cpus-per-run" {
+            parse_cpus_per_run = true;
+        } else if arg == "--runner-cpus" {
+            parse_cpu_groups = true;
+        } else if arg == "--runner-set-threads" {
+            set_threads = true;
+        } else if arg == "--runner-slots" {
+            parse_slot_pool_arg = true;
+        } else if arg == "--runner-capacity" {
+            parse_capacity = true;
+        } else if arg == "--runner-resources" {
+            parse_resources_arg = true;
+            scope = arg_scope;
+        } else if arg == "--runner-repeat" {
+            parse_repeat = true;
+        } else if arg == "--runner-seed-option" {
+            parse_seed_option = true;
+        } else if arg == "--runner-option-format" {
+            parse_option_format = true;
+        } else if arg == "--runner-hydra" {
+            hydra = true;
+        } else if arg == "--runner-hydra-config-dir" {
+            parse_hydra_config_dir = true;
+        } else if arg == "--runner-table" {
+            parse_table_path = true;
+        } else if arg == "--runner-export" {
+            parse_export_format = true;
+        } else if arg == "--runner-export-file" {
+            parse_export_file = true;
+        } else if arg == "--runner-after" {
+            parse_after_commands = true;
+        } else if arg == "--runner-creates" {
+            parse_creates = true;
+        } else if arg == "--runner-depends" {
+            parse_depends = true;
+        } else if arg == "--runner-force" {
+            force = true;
+        } else if arg == "--runner-sweep" {
+            parse_sweep_name = true;
+        } else if arg == "--runner-workdir" {
+            parse_workdir = true;
+        } else if arg == "--runner-workdir-copy" {
+            parse_workdir_copy_file = true;
+        } else if arg == "--runner-workdir-link" {
+            parse_workdir_link_file = true;
+        } else if arg == "--runner-shell" {
+            shell = Some("sh".to_string());
+            parse_shell = true;
+        } else {
+            new_command_args.push(arg.clone());
+        }
+    }
+    //// The groups of CPUs of the runs are a slot pool.
+    let allowed_cpus = sys::get_allowed_cpus();
+    let cpu_slots: Option<Vec<Vec<usize>>> = if let Some(cpu_groups) = &cpu_groups {
+        let cpu_slots: Vec<Vec<usize>> = cpu_groups.split('|').map(parse_cpu_list).collect();
+        if let Some(cpu) = cpu_slots.iter().flatten().find(|x| !allowed_cpus.contains(x)) {
+            println!("Error: CPU {} is not available to runner (available: {:?}).", cpu, allowed_cpus);
+            exit(1);
+        }
+        Some(cpu_slots)
+    } else if let Some(cpus_per_run) = cpus_per_run {
+        if cpus_per_run > allowed_cpus.len() {
+            println!(
+                "Error: --runner-cpus-per-run {} is greater than the number of available CPUs ({}).",
+                cpus_per_run, allowed_cpus.len()
+            );
+            exit(1);
+        }
+        // The remaining CPUs are not used.
+        Some(allowed_cpus.chunks_exact(cpus_per_run).map(|x| x.to_vec()).collect())
+    } else {
+        None
+    };
+    if let Some(cpu_slots) = &cpu_slots {
+        let cpu_slot_strings = cpu_slots.iter()
+            .map(|x| x.iter().map(|cpu| cpu.to_string()).collect::<Vec<String>>().join(","))
+            .collect();
+        slot_pools.push((CPU_SLOT_POOL.to_string(), cpu_slot_strings));
+    } else if set_threads {
+        println!("Error: --runner-set-threads requires --runner-cpus-per-run or --runner-cpus.");
+        exit(1);
+    }
+    let shared_pools: Vec<pool::SharedPool> = shared_pool_args.iter()
+        .map(|x| pool::parse_shared_pool(x, &pools_dir))
+        .collect();
+    // The slots are released when runner exits, so the commands left running
+    // in the background would exceed the size of the pools.
+    if !shared_pools.is_empty() && bg_run {
+        println!("Error: --runner-pool cannot be used with --bg-runner.");
+        exit(1);
+    }
+    let mut command_args = new_command_args;
+    // In Hydra mode, the multirun flag separates the command and the overrides,
+    // unless they are separated by `--`. The last flag followed by an override
+    // is used, since the command may also contain `-m` (e.g. `python -m
+    // pkg.train`).
+    if hydra && !command_args.contains(&"--".to_string()) {
+        let is_override = |x: &String| x.contains('=') || x.starts_with('~');
+        let idx = (0..command_args.len().saturating_sub(1)).rev().find(|&i| {
+            (command_args[i] == "-m" || command_args[i] == "--multirun") && is_override(&command_args[i + 1])
+        });
+        if let Some(idx) = idx {
+            command_args[idx] = "--".to_string();
+        }
+    }
+    // GNU parallel input sources (e.g. `::: a b ::: x y`).
+    let parallel_grid = parallel::parse_parallel_args(&mut command_args);
+    if hydra && table_path.is_none() && !command_args.contains(&"--".to_string()) {
+        println!("Error: --runner-hydra requires the overrides after the multirun flag (-m or --multirun) or after `--`.");
+        exit(1);
+    }
+    // With a table of runs, the grid arguments are optional.
+    let table_runs = table_path.as_ref().map(|x| table::read_table(x));
+    if table_runs.is_some() && !command_args.contains(&"--".to_string()) {
+        command_args.push("--".to_string());
+    }
+    let mut commands = Vec::new();
+    // The command is the string before the first argument that starts with a
+    // dash.
+    let mut i = 0;
+    let mut current_command = Vec::new();
+    while i<command_args.len() {
+        // Check if command argument is equal to "--".
+        if command_args[i] == "--" {
+            commands.push(current_command.clone());
+            i += 1;
+            break;
+        // Allow for multiple commands to be run.
+        } else if command_args[i] == "," {
+            commands.push(current_command.clone());
+            current_command = Vec::new();
+            i += 1;
+            continue;
+        }
+        current_command.push(command_args[i].clone());
+        i += 1;
+    }
+    // Commands can be named (e.g. train=train.py) to refer to them in the
+    // command-specific options.
+    let command_names: Vec<String> = commands.iter_mut().map(|x| split_command_name(x)).collect();
+    for (i, name) in command_names.iter().enumerate() {
+        if !name.is_empty() && command_names[..i].contains(name) {
+            println!("Error: duplicate command name: {}", name);
+            exit(1);
+        }
+    }
+    //// Runner settings of each command: the global ones, overridden by the
+    //// scoped ones.
+    let get_scope_commands = |scope: &Option<String>, option: &str| match scope {
+        Some(selector) => parse_command_selector(selector, &format!("--{},{}", selector, option), &command_names),
+        None => (0..commands.len()).collect(),
+    };
+    let mut command_settings: Vec<CommandSettings> = commands.iter()
+        .map(|_| CommandSettings {
+            runners,
+            explicit_runners: false,
+            filter_combs: filter_combs.clone(),
+            allow_combs: allow_combs.clone(),
+            env: Vec::new(),
+            timeout: None,
+            resources: Vec::new(),
+        })
+        .collect();
+    for (selector, command_runners) in &command_runners {
+        for i in parse_command_selector(selector, &format!("--runners {}:{}", selector, command_runners), &command_names) {
+            command_settings[i].runners = *command_runners;
+            command_settings[i].explicit_runners = true;
+        }
+    }
+    // Without a total, the runners of the commands are added.
+    if !total_runners && !command_runners.is_empty() {
+        runners = command_settings.iter().map(|x| x.runners).sum();
+    }
+    for (selector, comb) in &scoped_filter_combs {
+        for i in get_scope_commands(&Some(selector.clone()), "filter-runs") {
+            command_settings[i].filter_combs.push(comb.clone());
+        }
+    }
+    for (selector, comb) in &scoped_allow_combs {
+        for i in get_scope_commands(&Some(selector.clone()), "allow-runs") {
+            command_settings[i].allow_combs.push(comb.clone());
+        }
+    }
+    for (scope, name, value) in &env_vars {
+        for i in get_scope_commands(scope, "runner-env") {
+            command_settings[i].env.push((name.clone(), value.clone()));
+        }
+    }
+    for (scope, timeout) in &timeouts {
+        for i in get_scope_commands(scope, "runner-timeout") {
+            command_settings[i].timeout = Some(*timeout);
+        }
+    }
+    for (scope, command_resources) in &resources {
+        for i in get_scope_commands(scope, "runner-resources") {
+            command_settings[i].resources.push(command_resources.clone());
+        }
+    }
+    // With a capacity, the runs are limited by their resources instead of by
+    // the number of runners, unless it is given.
+    let capacity_resources = capacity.as_ref()
+        .map(|x| parse_resources(x, "--runner-capacity"))
+        .unwrap_or_default();
+    let mut unlimited_runners = capacity.is_some() && !total_runners && command_runners.is_empty();
+    // The commands that do not request resources keep the default number of
+    // runners, since they would not be limited otherwise.
+    let limited_commands: Vec<usize> = (0..command_settings.len())
+        .filter(|x| unlimited_runners && command_settings[*x].resources.is_empty())
+        .collect();
+    if unlimited_runners {
+        for settings in command_settings.iter_mut().filter(|x| !x.resources.is_empty()) {
+            settings.runners = usize::MAX;
+        }
+        runners = usize::MAX;
+    }
+    // Each running command holds a slot of each pool, so there can be no more
+    // runners than slots.
+    if let Some(slots) = slot_pools.iter().map(|x| x.1.len()).min() {
+        if !total_runners && command_runners.is_empty() && capacity.is_none() {
+            runners = slots;
+            for settings in &mut command_settings {
+                settings.runners = slots;
+            }
+        }
+    }
+    // The remaining arguments are the arguments for the command, which can be
+    // split into several grids whose combinations are joined.
+    let mut grids_args = vec![Vec::new()];
+    let mut j = i;
+    while j < command_args.len() {
+        if command_args[j..].starts_with(&GRID_SEPARATOR.map(String::from)) {
+            grids_args.push(Vec::new());
+            j += GRID_SEPARATOR.len();
+            continue;
+        }
+        grids_args.last_mut().unwrap().push(command_args[j].clone());
+        j += 1;
+    }
+    let mut grids = Vec::new();
+    if let Some(parallel_grid) = parallel_grid {
+        grids_args = Vec::new();
+        grids.push(parallel_grid);
+    }
+    for (grid_idx, grid_args) in grids_args.iter().enumerate() {
+        if hydra {
+            grids.push(hydra::parse_hydra_grid(grid_args, &hydra_config_dir));
+        } else {
+            grids.push(parse_grid(grid_args, runners, grid_idx, &command_names));
+        }
+    }
+
+    // Print the command that will be executed.
+    for (c, name) in commands.iter().zip(&command_names) {
+        if name.is_empty() {
+            println!("$ {}", shell::quote_command(c));
+        } else {
+            println!("$ {}  # {}", shell::quote_command(c), name);
+        }
+    }
+    for (grid_idx, grid) in grids.iter().enumerate() {
+        if grids.len() > 1 {
+            println!("Grid {}:", grid_idx);
+        }
+        print_grid(grid);
+    }
+    if let Some(table_runs) = &table_runs {
+        println!("  {}: {} runs (table)", table_path.as_ref().unwrap(), table_runs.len());
+        for (command_idx, _) in table_runs {
+            if command_idx.is_some_and(|x| x >= commands.len()) {
+                println!(
+                    "Error: invalid command index {} in {}.",
+                    command_idx.unwrap(),
+                    table_path.as_ref().unwrap()
+                );
+                exit(1);
+            }
+        }
+    }
+    println!();
+
+    if unlimited_runners {
+        println!("Number of runners: unlimited (limited by the resources)");
+        if !limited_commands.is_empty() {
+            let limited_names: Vec<String> = limited_commands.iter().map(|x| x.to_string()).collect();
+            println!(
+                "  {} for the commands without resources: {}",
+                command_settings[limited_commands[0]].runners,
+                limited_names.join(", ")
+            );
+        }
+    } else {
+        println!("Number of runners: {}", runners);
+    }
+    println!();
+    let has_process_limits = !process_limits.rlimits.is_empty()
+        || process_limits.nice.is_some()
+        || process_limits.io_priority.is_some();
+    if max_load.is_some() || min_free_mem.is_some() || stagger.is_some() {
+        let mut throttle_strings = Vec::new();
+        if let Some(max_load) = max_load {
+            throttle_strings.push(format!("max load {}", max_load));
+        }
+        if let Some(min_free_mem) = &min_free_mem {
+            throttle_strings.push(format!("min free memory {}", min_free_mem));
+        }
+        if let Some(stagger) = stagger {
+            throttle_strings.push(format!("stagger {}s", stagger.as_secs_f64()));
+        }
+        println!("Throttling: {}", throttle_strings.join(", "));
+        println!();
+    }
+    if has_process_limits {
+        let mut limit_strings = limit_args.clone();
+        if let Some(nice) = process_limits.nice {
+            limit_strings.push(format!("nice={}", nice));
+        }
+        if let Some((class, level)) = process_limits.io_priority {
+            let class_name = sys::IO_CLASSES.iter().find(|x| x.1 == class).unwrap().0;
+            limit_strings.push(format!("ionice={}:{}", class_name, level));
+        }
+        println!("Limits: {}", limit_strings.join(", "));
+        println!();
+    }
+    if let Some(control_file) = &control_file {
+        println!("Control file: {}", control_file.display());
+        println!();
+    }
+    if !shared_pools.is_empty() {
+        println!("Shared pools ({}):", pools_dir.display());
+        for shared_pool in &shared_pools {
+            println!("  {}: {}", shared_pool.name, shared_pool.size);
+        }
+        println!();
+    }
+    if !slot_pools.is_empty() {
+        println!("Slots:");
+        for (name, slots) in &slot_pools {
+            println!("  {}: {:?}", name, slots);
+        }
+        println!();
+    }
+    if let Some(capacity) = &capacity {
+        println!("Capacity: {}", capacity);
+        for (scope, command_resources) in &resources {
+            match scope {
+                Some(selector) => println!("  requested: {} (commands: {})", command_resources, selector),
+                None => println!("  requested: {}", command_resources),
+            }
+        }
+        println!();
+    }
+    if let Some(option_format) = &option_format {
+        println!("Option format: {}", option_format);
+        println!();
+    }
+    // Pipelines fail if any of their commands fails, where the shell supports
+    // it (e.g. bash, or recent versions of dash).
+    let shell_pipefail = shell.as_ref().is_some_and(|shell| {
+        Command::new(shell)
+            .args(["-o", "pipefail", "-c", ":"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|x| x.success())
+    });
+    if let Some(shell) = &shell {
+        println!("Shell: {}", shell);
+        if !shell_pipefail {
+            println!(
+                "Warning: {} does not support pipefail, so pipelines only fail if their last command fails.",
+                shell
+            );
+        }
+        println!();
+    }
+    if !after_commands.is_empty() {
+        println!("Dependencies:");
+        for (command, dependencies) in &after_commands {
+            if *command >= commands.len() {
+                println!("Error: invalid command index {} in --runner-after.", command);
+                exit(1);
+            }
+            let dependencies: Vec<String> = dependencies.iter().map(|x| x.to_string()).collect();
+            println!("  {} after {}", command, dependencies.join(", "));
+        }
+        println!();
+    }
+    if let Some(creates) = &creates {
+        print!("Creates: {}", creates);
+        if !depends.is_empty() {
+            print!(" (depends on: {})", depends.join(", "));
+        }
+        if force {
+            print!(" (forced)");
+        }
+        println!();
+        println!();
+    }
+    if let Some(workdir) = &workdir {
+        println!("Working directories: {}", workdir);
+        for file in &workdir_copy_files {
+            println!("  copy: {}", file);
+        }
+        for file in &workdir_link_files {
+            println!("  link: {}", file);
+        }
+        println!();
+    }
+    if repeat > 1 {
+        print!("Replicas per combination: {}", repeat);
+        if let Some(seed_option) = &seed_option {
+            print!(" ({} <replica>)", seed_option);
+        }
+        println!();
+        println!();
+    }
+
+    if !filter_combs.is_empty() || !scoped_filter_combs.is_empty() {
+        println!("Filter runs:");
+        for comb in &filter_combs {
+            println!("  \"{}\"", comb.join(","));
+        }
+        for (selector, comb) in &scoped_filter_combs {
+            println!("  \"{}\" (commands: {})", comb.join(","), selector);
+        }
+        println!();
+    }
+    if !allow_combs.is_empty() || !scoped_allow_combs.is_empty() {
+        println!("Allow runs:");
+        for comb in &allow_combs {
+            println!("  \"{}\"", comb.join(","));
+        }
+        for (selector, comb) in &scoped_allow_combs {
+            println!("  \"{}\" (commands: {})", comb.join(","), selector);
+        }
+        println!();
+    }
+    // Settings that are not shared by all the commands.
+    if !command_runners.is_empty() || !env_vars.is_empty() || !timeouts.is_empty() {
+        println!("Command settings:");
+        for (i, settings) in command_settings.iter().enumerate() {
+            let mut command_settings_strings = Vec::new();
+            if !unlimited_runners {
+                command_settings_strings.push(format!("runners {}", settings.runners));
+            }
+            if let Some(timeout) = settings.timeout {
+                command_settings_strings.push(format!("timeout {}s", timeout.as_secs_f64()));
+            }
+            for (name, value) in &settings.env {
+                command_settings_strings.push(format!("{}={}", name, shell::shell_quote(value)));
+            }
+            println!("  {}: {}", i, command_settings_strings.join(", "));
+        }
+        println!();
+    }
+
+    let mut commands_run = 0;
+    let mut skipped_runs = 0;
+    let mut not_run_runs = 0;
+    let mut timed_out_runs = 0;
+    // Commands killed by a limit: (command, description of the limit).
+    let mut limit_killed_commands = Vec::new();
+    // Maximum number of commands that run in parallel.
+    let mut max_running = 0;
+    let mut interrupted_runs = 0;
+    // Array of commands that are currently running.
+    let mut running_commands: Vec<RunningCommand> = Vec::new();
+    let mut failed_commands = Vec::new();
+    let mut combinations_sets = BTreeMap::new();
+    let joined_options: Vec<String> = grids.iter()
+        .flat_map(|grid| grid.joined_args.clone())
+        .collect();
+    let placeholder_options: Vec<String> = grids.iter()
+        .flat_map(|grid| grid.placeholder_args.clone())
+        .collect();
+    let toggle_options: Vec<String> = grids.iter()
+        .flat_map(|grid| grid.multi_args.values())
+        .filter(|(_, values)| values == &[FLAG_VALUE, NO_FLAG_VALUE])
+        .map(|(option, _)| option.clone())
+        .collect();
+    for (command_index, command) in commands.iter().enumerate() {
+        //// Print the command that will be executed.
+        print!("$ {} ", shell::quote_command(command));
+
+        //// Join the combinations of all the grids, without duplicates.
+        let mut combinations = Vec::new();
+        for grid in &grids {
+            let grid_combinations = get_combinations(
+                grid,
+                command_index,
+                &command_settings[command_index].filter_combs,
+                &command_settings[command_index].allow_combs
+            );
+            for combination in grid_combinations {
+                if !combinations.contains(&combination) {
+                    combinations.push(combination);
                 }
             }
-            if !match_found {
-                combinations.push(this_comb);
+        }
+        //// Add the runs of the table, unless they are excluded by the rules.
+        for (command_idx, combination) in table_runs.iter().flatten() {
+            if command_idx.is_some_and(|x| x != command_index) {
+                continue;
+            }
+            // Flags are referred to by their name in the rules.
+            let option_values: Vec<String> = combination.iter()
+                .map(|(key, value)| if value.is_empty() {
+                    key.trim_start_matches('-').to_string()
+                } else {
+                    value.clone()
+                })
+                .collect();
+            let settings = &command_settings[command_index];
+            if !is_excluded(&option_values, &settings.filter_combs, &settings.allow_combs)
+                && !combinations.contains(combination)
+            {
+                combinations.push(combination.clone());
             }
         }
         if !combinations.is_empty() {
             println!("  # {} combinations", combinations.len());
             for combination in &combinations {
                 for (key, value) in combination {
-                    print!("  {} {}", key, value.replace(&separator_string, " "));
+                    print!("  {} {}", key, value.replace(SEPARATOR, " "));
                 }
                 println!();
             }
@@ -655,7 +2373,6 @@ fn main() {
         println!();
 
         combinations_sets.insert(command_index, (command.clone(), combinations));
-        command_index += 1;
     } // end for command in commands
 
     if info_only {
@@ -663,82 +2380,615 @@ fn main() {
     }
 
 
-    //// Run the commands.
-    for (_idx, combinations_set) in combinations_sets {
+    //// Directory of the sweep (e.g. outputs/2024-05-01/13-45-10_name).
+    let sweep_dir = sweep_name.as_ref().map(|name| {
+        let date_time_path = time_utils::get_date_time_path(SystemTime::now());
+        let mut sweep_dir = PathBuf::from(format!("outputs/{}_{}", date_time_path, name));
+        // Sweeps started in the same second get a numeric suffix.
+        let mut suffix = 1;
+        while sweep_dir.exists() {
+            sweep_dir = PathBuf::from(format!("outputs/{}_{}_{}", date_time_path, name, suffix));
+            suffix += 1;
+        }
+        sweep_dir
+    });
+    if let Some(sweep_dir) = &sweep_dir {
+        println!("Sweep directory: {}", sweep_dir.display());
+    }
+    // The runs get the absolute path, since they may change their working
+    // directory.
+    let sweep_dir_string = sweep_dir.as_ref()
+        .map(|x| env::current_dir().unwrap().join(x).to_string_lossy().to_string());
+
+    //// Plan the runs of the commands.
+    // Replicas of each combination: (combination string, failed replicas).
+    let mut replica_groups: Vec<(String, Vec<usize>)> = Vec::new();
+    let mut runs = Vec::new();
+    for (command_idx, combinations_set) in combinations_sets {
         let command = combinations_set.0;
         let combinations = combinations_set.1;
 
         for combination in &combinations {
-            // Get c as string
-            let mut command_obj = Command::new(&command[0]);
-            for arg in &command[1..] {
-                command_obj.arg(arg);
-            }
+            let mut combination_args = command.clone();
             for (key, value) in combination {
-                // Add the key if it is not an empty string.
                 if !key.is_empty() {
-                    command_obj.arg(key);
+                    combination_args.push(key.clone());
                 }
                 if !value.is_empty() {
-                    if value.contains(&separator_string) {
-                        let real_values: Vec<&str> = value.split(&separator_string).collect();
-                        for real_value in real_values {
-                            command_obj.arg(real_value);
+                    combination_args.extend(value.split(SEPARATOR).map(String::from));
+                }
+            }
+            let combination_string = shell::quote_command(&combination_args);
+            replica_groups.push((combination_string, Vec::new()));
+            let group_idx = replica_groups.len() - 1;
+            for replica in 0..repeat {
+                // Placeholders: the options (without dashes) and the replica.
+                //   Flags are `true`, and absent toggleable flags `false`.
+                let mut placeholders = Vec::new();
+                for (key, value) in combination {
+                    placeholders.push((
+                        key.trim_start_matches('-').to_string(),
+                        if value.is_empty() { "true".to_string() } else { value.replace(SEPARATOR, " ") }
+                    ));
+                }
+                for option in &toggle_options {
+                    if !combination.iter().any(|(key, _)| key == option) {
+                        placeholders.push((option.trim_start_matches('-').to_string(), "false".to_string()));
+                    }
+                }
+                placeholders.push(("replica".to_string(), replica.to_string()));
+                placeholders.push(("run_id".to_string(), runs.len().to_string()));
+                let mut env = vec![("RUNNER_REPLICA".to_string(), replica.to_string())];
+                if let Some(sweep_dir_string) = &sweep_dir_string {
+                    placeholders.push(("sweep_dir".to_string(), sweep_dir_string.clone()));
+                    env.push(("RUNNER_SWEEP_DIR".to_string(), sweep_dir_string.clone()));
+                }
+                env.extend(command_settings[command_idx].env.clone());
+                let mut argv = Vec::new();
+                for arg in &command {
+                    argv.push(fill_placeholders(arg, &placeholders));
+                }
+                let mut combination = combination.clone();
+                if let Some(seed_option) = &seed_option {
+                    combination.push((seed_option.clone(), replica.to_string()));
+                }
+                for (key, value) in &combination {
+                    // Placeholder arguments are only inserted through placeholders.
+                    if placeholder_options.contains(key) {
+                        continue;
+                    }
+                    let key_format = if joined_options.contains(key) {
+                        Some("{option}={value}")
+                    } else {
+                        option_format.as_deref()
+                    };
+                    // Join the key with each of its values.
+                    if let Some(key_format) = key_format {
+                        if !key.is_empty() && !value.is_empty() {
+                            for real_value in value.split(SEPARATOR) {
+                                let real_value = fill_placeholders(real_value, &placeholders);
+                                argv.push(format_option(key_format, key, &real_value));
+                            }
+                            continue;
                         }
+                    }
+                    // Add the key if it is not an empty string.
+                    if !key.is_empty() {
+                        argv.push(key.clone());
+                    }
+                    if !value.is_empty() {
+                        if value.contains(SEPARATOR) {
+                            let real_values: Vec<&str> = value.split(SEPARATOR).collect();
+                            for real_value in real_values {
+                                argv.push(fill_placeholders(real_value, &placeholders));
+                            }
 
-                    } else {
-                        command_obj.arg(value);
+                        } else {
+                            argv.push(fill_placeholders(value, &placeholders));
+                        }
+                    }
+                }
+                // In shell mode, the command is a shell script where the
+                //   arguments are inserted quoted.
+                if let Some(shell) = &shell {
+                    let mut script = command.join(" ");
+                    if !script.contains("{args}") {
+                        script.push_str(" {args}");
+                    }
+                    let mut shell_placeholders: Vec<(String, String)> = placeholders.iter()
+                        .map(|(key, value)| (key.clone(), shell::shell_quote(value)))
+                        .collect();
+                    let args: Vec<String> = argv[command.len()..].iter()
+                        .map(|x| shell::shell_quote(x))
+                        .collect();
+                    shell_placeholders.push(("args".to_string(), args.join(" ")));
+                    let script = fill_placeholders(&script, &shell_placeholders);
+                    argv = vec![shell.clone()];
+                    if shell_pipefail {
+                        argv.extend(["-o".to_string(), "pipefail".to_string()]);
+                    }
+                    argv.extend(["-c".to_string(), script.trim_end().to_string()]);
+                }
+                let run_workdir = workdir.as_ref().map(|x| fill_placeholders(x, &placeholders));
+                let mut run_resources: Vec<(String, f64)> = Vec::new();
+                for command_resources in &command_settings[command_idx].resources {
+                    let command_resources = fill_placeholders(command_resources, &placeholders);
+                    for resource in parse_resources(&command_resources, "--runner-resources") {
+                        run_resources.retain(|x| x.0 != resource.0);
+                        run_resources.push(resource);
                     }
                 }
+                runs.push(Run {
+                    argv,
+                    env,
+                    combination,
+                    command_idx,
+                    group_idx,
+                    replica,
+                    workdir: run_workdir,
+                    creates: creates.as_ref().map(|x| fill_placeholders(x, &placeholders)),
+                    depends: depends.iter().map(|x| fill_placeholders(x, &placeholders)).collect(),
+                    after: Vec::new(),
+                    timeout: command_settings[command_idx].timeout,
+                    resources: run_resources,
+                });
             }
+        }
+    }
+    //// Each run depends on the runs of the previous commands with the same
+    //// combination (i.e., the same values in their common options).
+    for run_idx in 0..runs.len() {
+        let dependencies = match after_commands.get(&runs[run_idx].command_idx) {
+            Some(dependencies) => dependencies,
+            None => continue,
+        };
+        let after: Vec<usize> = (0..run_idx)
+            .filter(|&x| dependencies.contains(&runs[x].command_idx)
+                && runs[x].replica == runs[run_idx].replica
+                && is_same_combination(&runs[x].combination, &runs[run_idx].combination))
+            .collect();
+        runs[run_idx].after = after;
+    }
+    //// The resources requested by each run must be available in the capacity.
+    if capacity.is_some() {
+        for run in &runs {
+            for (name, amount) in &run.resources {
+                let available = capacity_resources.iter()
+                    .find(|x| &x.0 == name)
+                    .map_or(0.0, |x| x.1);
+                if *amount > available {
+                    println!(
+                        "Error: the run requests {} {}, but the capacity is {}: $ {}",
+                        amount, name, available, shell::quote_command(&run.argv)
+                    );
+                    exit(1);
+                }
+            }
+        }
+    } else if runs.iter().any(|x| !x.resources.is_empty()) {
+        println!("Error: --runner-resources requires --runner-capacity.");
+        exit(1);
+    }
+    // The working directories must not be shared by several runs.
+    if workdir.is_some() {
+        let mut workdirs = BTreeSet::new();
+        for run in &runs {
+            let run_workdir = run.workdir.as_ref().unwrap();
+            if !workdirs.insert(run_workdir) {
+                println!(
+                    "Error: several runs have the working directory {} (use {{run_id}} in --runner-workdir).",
+                    run_workdir
+                );
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(export_format) = &export_format {
+        let export_path = export_file.unwrap_or(format!("runner_plan.{}", export_format));
+        let export_string = match export_format.as_str() {
+            "json" => export::to_json(&runs),
+            "csv" => export::to_csv(&runs),
+            _ => export::to_shell_script(&runs),
+        };
+        let mut file = File::create(&export_path).unwrap();
+        file.write_all(export_string.as_bytes()).unwrap();
+        println!("{} runs exported to {}.", runs.len(), export_path);
+        exit(0);
+    }
+
+    // The working directories are fresh, so that the runs are isolated from
+    // previous sweeps, except for runs with outputs (--runner-creates), which
+    // are skipped or updated in place.
+    if !dry_run {
+        for run in runs.iter().filter(|x| x.creates.is_none()) {
+            let run_workdir = match &run.workdir {
+                Some(run_workdir) => run_workdir,
+                None => continue,
+            };
+            if fs::read_dir(run_workdir).is_ok_and(|mut x| x.next().is_some()) {
+                println!(
+                    "Error: the working directory {} already exists and is not empty.",
+                    run_workdir
+                );
+                exit(1);
+            }
+        }
+    }
+
+    //// Create the sweep directory, with the plan of the runs.
+    if let Some(sweep_dir) = &sweep_dir {
+        if !dry_run {
+            if let Err(why) = fs::create_dir_all(sweep_dir.join("logs")) {
+                println!("Error: couldn't create {}: {}", sweep_dir.display(), why);
+                exit(1);
+            }
+            fs::write(sweep_dir.join("plan.json"), export::to_json(&runs)).unwrap();
+        }
+    }
+
+    if !dry_run {
+        for shared_pool in &shared_pools {
+            pool::create_pool_dir(shared_pool);
+        }
+    }
+
+    //// Run the commands, each one as soon as its dependencies have succeeded.
+    // Commands with a timeout run in their own process group, so the
+    //   interrupts are forwarded to them.
+    if !dry_run && runs.iter().any(|x| x.timeout.is_some()) {
+        sys::catch_interrupts();
+    }
+    // The number of runners can be changed while running.
+    if !dry_run {
+        sys::catch_runners_changes();
+        if let Some(control_file) = control_file.as_ref().filter(|_| !unlimited_runners) {
+            if let Err(e) = fs::write(control_file, format!("{}\n", runners)) {
+                println!("Error: couldn't write {}: {}", control_file.display(), e);
+                exit(1);
+            }
+        }
+    }
+    let mut control_file_time = None;
+    let mut written_hold_reason = None;
+    let mut handled_interrupts = 0;
+    let mut last_launch_time = None;
+    let mut is_holding = false;
+    let mut run_states = vec![RunState::Pending; runs.len()];
+    let mut pending_runs: Vec<usize> = (0..runs.len()).collect();
+    // In the background, runner does not wait for the last commands.
+    let wait_all = dry_run || !bg_run;
+    while !pending_runs.is_empty() || (wait_all && !running_commands.is_empty()) {
+        //// The first interrupt is forwarded to the commands, and the next
+        //// ones kill them.
+        let interrupts = sys::get_interrupts();
+        if interrupts > handled_interrupts {
+            handled_interrupts = interrupts;
+            println!();
+            println!("Interrupted: stopping the commands...");
+            let signum = if interrupts == 1 { sys::SIGINT } else { sys::SIGKILL };
+            for running_command in &mut running_commands {
+                if runs[running_command.run_idx].timeout.is_some() {
+                    sys::signal_process_group(&mut running_command.child, signum);
+                }
+            }
+            interrupted_runs += pending_runs.len();
+            pending_runs.clear();
+        }
+        //// The number of runners is changed by signals or by the control file,
+        //// without stopping the running commands.
+        let mut new_runners = None;
+        let runners_change = sys::take_runners_change();
+        if runners_change != 0 {
+            // Unlimited runners are changed from the running commands.
+            let current_runners = if unlimited_runners { running_commands.len() } else { runners };
+            new_runners = Some(current_runners.saturating_add_signed(runners_change));
+        }
+        if let Some(control_file) = control_file.as_ref().filter(|_| !dry_run) {
+            let modified_time = fs::metadata(control_file).and_then(|x| x.modified()).ok();
+            if modified_time != control_file_time {
+                control_file_time = modified_time;
+                // Files being written may be empty.
+                let content = fs::read_to_string(control_file).unwrap_or_default();
+                match content.trim().parse::<usize>() {
+                    Ok(file_runners) => new_runners = Some(file_runners),
+                    Err(_) if content.trim().is_empty() => (),
+                    Err(_) => println!(
+                        "Error: invalid number of runners in {}: {}",
+                        control_file.display(),
+                        content.trim()
+                    ),
+                }
+            }
+        }
+        if let Some(new_runners) = new_runners.filter(|x| *x != runners) {
+            runners = new_runners;
+            unlimited_runners = false;
+            set_runners(&mut command_settings, runners);
             println!();
-            if dry_run {
-                print_command(&command_obj);
+            if runners == 0 {
+                println!("Number of runners changed to 0: the launches are paused.");
             } else {
-                if running_commands.len() >= runners {
-                    // Wait for a command to finish.
-                    let mut child: (Child, String) = running_commands.remove(0);
-                    //https://doc.rust-lang.org/std/process/struct.Child.html
-                    if !wait_for_child(&mut child.0) {
-                        failed_commands.push(child.1);
+                println!("Number of runners changed to {}.", runners);
+            }
+            if let Some(control_file) = &control_file {
+                let _ = fs::write(control_file, format!("{}\n", runners));
+            }
+        }
+        //// The commands are terminated after their timeout, and killed if
+        //// they do not exit.
+        for running_command in &mut running_commands {
+            let timeout = match runs[running_command.run_idx].timeout {
+                Some(timeout) => timeout,
+                None => continue,
+            };
+            match running_command.terminated_time {
+                None if running_command.start_time.elapsed().unwrap_or_default() > timeout => {
+                    println!();
+                    println!(
+                        "Timed out after {}s: $ {}",
+                        timeout.as_secs_f64(),
+                        running_command.command_string
+                    );
+                    sys::signal_process_group(&mut running_command.child, sys::SIGTERM);
+                    running_command.terminated_time = Some(SystemTime::now());
+                    timed_out_runs += 1;
+                },
+                Some(terminated_time)
+                    if terminated_time.elapsed().unwrap_or_default() > KILL_GRACE_PERIOD =>
+                {
+                    sys::signal_process_group(&mut running_command.child, sys::SIGKILL);
+                },
+                _ => (),
+            }
+        }
+        // Runs whose dependencies failed are not run.
+        pending_runs.retain(|&run_idx| {
+            let run = &runs[run_idx];
+            if !run.after.iter().any(|&x| run_states[x] == RunState::Failed) {
+                return true;
+            }
+            println!();
+            println!("{}", "-".repeat(80));
+            println!("Not run (a dependency failed): $ {}", shell::quote_command(&run.argv));
+            write_journal(sweep_dir.as_deref(), &format!("not-run {}", run_idx));
+            run_states[run_idx] = RunState::Failed;
+            replica_groups[run.group_idx].1.push(run.replica);
+            not_run_runs += 1;
+            false
+        });
+        // The next run is the first one whose dependencies have succeeded, if
+        //   there are runners left for it.
+        let free_slots = slot_pools.iter().map(|x| x.1.len()).min().unwrap_or(usize::MAX);
+        let running_runs: Vec<usize> = running_commands.iter().map(|x| x.run_idx).collect();
+        let ready_idx = find_ready_run(
+            &pending_runs,
+            &running_runs,
+            &runs,
+            &run_states,
+            &command_settings,
+            runners.min(free_slots),
+            &capacity_resources,
+        );
+        // The launches are held while the machine is busy, and staggered.
+        let mut hold_reason = None;
+        let mut pool_locks = Vec::new();
+        if ready_idx.is_some() && !dry_run {
+            hold_reason = get_launch_hold_reason(max_load, min_free_mem.as_deref());
+            let is_staggered = last_launch_time.is_some_and(|x: SystemTime| {
+                x.elapsed().unwrap_or_default() < stagger.unwrap_or_default()
+            });
+            if hold_reason.is_none() && is_staggered {
+                hold_reason = Some("stagger".to_string());
+            }
+            // A slot of each shared pool is locked, unless one is full.
+            if hold_reason.is_none() {
+                for shared_pool in &shared_pools {
+                    match pool::try_lock_slot(shared_pool) {
+                        Some(pool_lock) => pool_locks.push(pool_lock),
+                        None => {
+                            pool_locks.clear();
+                            hold_reason = Some(format!("shared pool {} is full", shared_pool.name));
+                            break;
+                        },
                     }
                 }
-                // Print the command that will be executed without the quotes.
-                let c_str = print_command(&command_obj);
-                let child = match command_obj
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .spawn()
-                    {
-                        Ok(child) => child,
-                        Err(e) => {
-                            // If the error is because the command was not found,
-                            // exit the program.
-                            if e.kind() == ErrorKind::NotFound {
-                                println!(
-                                    "Command not found: {}",
-                                    command_obj.get_program().to_str().unwrap()
-                                );
-                                println!("Exiting...");
-                                exit(1);
-                            } else {
-                                continue;
-                            }
+            }
+            match &hold_reason {
+                // The reason is printed once per hold, except for staggering.
+                Some(reason) if !is_holding && reason != "stagger" => {
+                    println!();
+                    println!("Holding the launches: {}", reason);
+                    is_holding = true;
+                },
+                None => is_holding = false,
+                _ => (),
+            }
+        }
+        // The reason is written to the hold file when it changes (empty if the
+        //   launches are not held).
+        if let Some(hold_file) = hold_file.as_ref().filter(|_| !dry_run) {
+            let hold_string = hold_reason.clone().unwrap_or_default();
+            if written_hold_reason.as_ref() != Some(&hold_string) {
+                if let Err(e) = fs::write(hold_file, format!("{}\n", hold_string)) {
+                    println!("Error: couldn't write {}: {}", hold_file.display(), e);
+                    exit(1);
+                }
+                written_hold_reason = Some(hold_string);
+            }
+        }
+        let run_idx = match ready_idx.filter(|_| hold_reason.is_none()) {
+            Some(ready_idx) => pending_runs.remove(ready_idx),
+            None => {
+                if running_commands.is_empty() {
+                    if hold_reason.is_some() || runners == 0 {
+                        thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                    break;
+                }
+                // Wait for a command to finish.
+                //https://doc.rust-lang.org/std/process/struct.Child.html
+                let finished_idx = running_commands.iter_mut()
+                    .position(|x| !matches!(x.child.try_wait(), Ok(None)));
+                match finished_idx {
+                    Some(finished_idx) => {
+                        let mut running_command = running_commands.remove(finished_idx);
+                        let run_idx = running_command.run_idx;
+                        let status = finish_run(&mut running_command, &runs, sweep_dir.as_deref());
+                        // Commands terminated by a signal may have been killed by
+                        //   a limit (but not by their timeout).
+                        let limit_description = status.as_ref()
+                            .and_then(sys::get_signal)
+                            .filter(|_| running_command.terminated_time.is_none())
+                            .and_then(|x| get_limit_description(x, &process_limits.rlimits));
+                        if let Some(limit_description) = limit_description {
+                            println!("Killed by a limit ({}): $ {}", limit_description, running_command.command_string);
+                            limit_killed_commands.push((running_command.command_string.clone(), limit_description));
                         }
-                    };
-                // Run command detached
-                running_commands.push((child, c_str));
+                        if status.is_some_and(|x| x.success()) {
+                            run_states[run_idx] = RunState::Succeeded;
+                        } else {
+                            run_states[run_idx] = RunState::Failed;
+                            failed_commands.push(running_command.command_string);
+                            replica_groups[runs[run_idx].group_idx].1.push(runs[run_idx].replica);
+                        }
+                    },
+                    None => thread::sleep(Duration::from_millis(20)),
+                }
+                continue;
+            }
+        };
+        let run = &runs[run_idx];
+        let mut program = run.argv[0].clone();
+        // Relative programs (e.g. ./train.py) are resolved before changing
+        // the working directory.
+        if run.workdir.is_some() && program.contains('/') && Path::new(&program).is_relative() {
+            if let Ok(path) = fs::canonicalize(&program) {
+                program = path.to_string_lossy().to_string();
+            }
+        }
+        // The first free slot of each pool is held by the run.
+        let mut slots = Vec::new();
+        let mut slot_placeholders = Vec::new();
+        for (pool_idx, (name, pool_slots)) in slot_pools.iter().enumerate() {
+            let slot = (0..pool_slots.len())
+                .find(|x| !running_commands.iter().any(|y| y.slots[pool_idx] == *x))
+                .unwrap();
+            slots.push(slot);
+            slot_placeholders.push((name.clone(), pool_slots[slot].clone()));
+        }
+        let mut command_obj = Command::new(&program);
+        command_obj.args(run.argv[1..].iter().map(|x| fill_placeholders(x, &slot_placeholders)));
+        command_obj.envs(run.env.iter().map(|(k, v)| (k, v)));
+        command_obj.envs(slot_placeholders.iter().map(|(k, v)| (k, v)));
+        if let Some(cpu_slots) = &cpu_slots {
+            let pool_idx = slot_pools.iter().position(|x| x.0 == CPU_SLOT_POOL).unwrap();
+            let cpus = &cpu_slots[slots[pool_idx]];
+            sys::set_cpu_affinity(&mut command_obj, cpus);
+            if set_threads {
+                for threads_env_var in THREADS_ENV_VARS {
+                    command_obj.env(threads_env_var, cpus.len().to_string());
+                }
             }
-            commands_run += 1;
         }
+        if let Some(run_workdir) = &run.workdir {
+            command_obj.current_dir(run_workdir);
+        }
+        if run.timeout.is_some() {
+            sys::set_process_group(&mut command_obj);
+        }
+        if has_process_limits {
+            sys::set_process_limits(&mut command_obj, &process_limits);
+        }
+        println!();
+        // The output is checked just before the run, since it may be
+        // created by previous runs.
+        if !force && is_up_to_date(run) {
+            // Dry runs do not create the sweep directory.
+            let journal_dir = sweep_dir.as_deref().filter(|_| !dry_run);
+            print_skipped_run(run, journal_dir, run_idx);
+            skipped_runs += 1;
+            run_states[run_idx] = RunState::Succeeded;
+            continue;
+        }
+        if dry_run {
+            print_command(&command_obj);
+            run_states[run_idx] = RunState::Succeeded;
+        } else {
+            if let Some(run_workdir) = &run.workdir {
+                create_workdir(run_workdir, &workdir_copy_files, &workdir_link_files);
+            }
+            let start_time = SystemTime::now();
+            write_run_json(run, start_time, None, None);
+            // Print the command that will be executed without the quotes.
+            let c_str = print_command(&command_obj);
+            if !slot_placeholders.is_empty() {
+                let slot_strings: Vec<String> = slot_placeholders.iter()
+                    .map(|(name, value)| format!("{}={}", name, shell::shell_quote(value)))
+                    .collect();
+                println!("Slots: {}", slot_strings.join(" "));
+            }
+            // In a sweep directory, the output of each run goes to its log.
+            let (stdout, stderr) = match &sweep_dir {
+                Some(sweep_dir) => {
+                    let log_path = sweep_dir.join("logs").join(format!("{}.log", run_idx));
+                    println!("Log: {}", log_path.display());
+                    let log_file = File::create(&log_path).unwrap();
+                    (Stdio::from(log_file.try_clone().unwrap()), Stdio::from(log_file))
+                },
+                None => (Stdio::inherit(), Stdio::inherit()),
+            };
+            let child = match command_obj
+                .stdout(stdout)
+                .stderr(stderr)
+                .spawn()
+                {
+                    Ok(child) => child,
+                    Err(e) => {
+                        // If the error is because the command was not found,
+                        // exit the program.
+                        if e.kind() == ErrorKind::NotFound {
+                            println!(
+                                "Command not found: {}",
+                                shell::shell_quote_os(command_obj.get_program())
+                            );
+                            println!("Exiting...");
+                            exit(1);
+                        } else {
+                            println!("Error: couldn't run the command: {}", e);
+                            run_states[run_idx] = RunState::Failed;
+                            failed_commands.push(c_str);
+                            replica_groups[run.group_idx].1.push(run.replica);
+                            continue;
+                        }
+                    }
+                };
+            write_journal(sweep_dir.as_deref(), &format!("start {} $ {}", run_idx, c_str));
+            // Run command detached
+            running_commands.push(RunningCommand {
+                child,
+                command_string: c_str,
+                run_idx,
+                start_time,
+                terminated_time: None,
+                slots,
+                _pool_locks: pool_locks,
+            });
+            max_running = max_running.max(running_commands.len());
+            last_launch_time = Some(SystemTime::now());
+            run_states[run_idx] = RunState::Running;
+        }
+        commands_run += 1;
+    }
+    if let Some(hold_file) = hold_file.as_ref().filter(|_| !dry_run) {
+        let _ = fs::write(hold_file, "\n");
     }
 
 
     if dry_run || !bg_run {
-        for mut child in running_commands {
-            if !wait_for_child(&mut child.0) {
-                failed_commands.push(child.1);
-            }
+        if unlimited_runners {
+            runners = max_running.max(1);
         }
         print!("\n{} commands run by {} runners.", commands_run, runners);
         if dry_run {
@@ -746,6 +2996,43 @@ fn main() {
         } else {
             println!();
         }
+        if skipped_runs > 0 {
+            println!("{} runs skipped (outputs up to date).", skipped_runs);
+        }
+        if not_run_runs > 0 {
+            println!("{} runs not run (a dependency failed).", not_run_runs);
+        }
+        if timed_out_runs > 0 {
+            println!("{} runs timed out.", timed_out_runs);
+        }
+        if interrupted_runs > 0 {
+            println!("{} runs not started (interrupted).", interrupted_runs);
+        }
+        if !limit_killed_commands.is_empty() {
+            println!("Killed by limits ({}):", limit_killed_commands.len());
+            for (command, limit_description) in &limit_killed_commands {
+                println!("  $ {} ({})", command, limit_description);
+            }
+        }
+        if repeat > 1 && !dry_run {
+            println!("Replicas ({} per combination):", repeat);
+            for (combination_string, failed_replicas) in &mut replica_groups {
+                failed_replicas.sort();
+                print!(
+                    "  {}/{} succeeded: $ {}",
+                    repeat - failed_replicas.len(),
+                    repeat,
+                    combination_string
+                );
+                if !failed_replicas.is_empty() {
+                    let failed: Vec<String> = failed_replicas.iter()
+                        .map(|x| x.to_string())
+                        .collect();
+                    print!(" (failed replicas: {})", failed.join(", "));
+                }
+                println!();
+            }
+        }
     }
     if !failed_commands.is_empty() {
         println!("Failed commands ({}):", failed_commands.len());
@@ -753,10 +3040,14 @@ fn main() {
             println!("  $ {}", command);
         }
         // Write failed commands to file. Append to file if it already exists.
+        let failed_log_path = match &sweep_dir {
+            Some(sweep_dir) if !dry_run => sweep_dir.join("runner_failed.log"),
+            _ => PathBuf::from("runner_failed.log"),
+        };
         let mut file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open("runner_failed.log")
+            .open(failed_log_path)
             .unwrap();
 
         // Convert to date
@@ -772,3 +3063,100 @@ fn main() {
         exit(2);
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_run(command_idx: usize, after: Vec<usize>, resources: Vec<(String, f64)>) -> Run {
+        Run {
+            argv: vec!["true".to_string()],
+            env: Vec::new(),
+            combination: Vec::new(),
+            command_idx,
+            group_idx: 0,
+            replica: 0,
+            workdir: None,
+            creates: None,
+            depends: Vec::new(),
+            after,
+            timeout: None,
+            resources,
+        }
+    }
+
+    fn test_settings<'a>(runners: usize, explicit_runners: bool) -> CommandSettings<'a> {
+        CommandSettings {
+            runners,
+            explicit_runners,
+            filter_combs: Vec::new(),
+            allow_combs: Vec::new(),
+            env: Vec::new(),
+            timeout: None,
+            resources: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn ready_run_limits() {
+        let runs = vec![test_run(0, vec![], vec![]), test_run(0, vec![], vec![]), test_run(1, vec![], vec![])];
+        let states = vec![RunState::Pending; 3];
+        let settings = vec![test_settings(1, true), test_settings(2, false)];
+        // The first run of command 0 is running, so the next is of command 1.
+        assert_eq!(find_ready_run(&[1, 2], &[0], &runs, &states, &settings, 2, &[]), Some(1));
+        // No runners left in total.
+        assert_eq!(find_ready_run(&[1, 2], &[0], &runs, &states, &settings, 1, &[]), None);
+        assert_eq!(find_ready_run(&[0, 1, 2], &[], &runs, &states, &settings, 0, &[]), None);
+    }
+
+    #[test]
+    fn ready_run_dependencies_and_resources() {
+        let gpus = |x: f64| vec![("gpus".to_string(), x)];
+        let runs = vec![test_run(0, vec![], gpus(2.0)), test_run(0, vec![0], vec![]), test_run(0, vec![], gpus(1.0))];
+        let settings = vec![test_settings(usize::MAX, false)];
+        let capacity = gpus(2.0);
+        let mut states = vec![RunState::Running, RunState::Pending, RunState::Pending];
+        // Run 1 waits for run 0, and run 2 for its resources.
+        assert_eq!(find_ready_run(&[1, 2], &[0], &runs, &states, &settings, usize::MAX, &capacity), None);
+        states[0] = RunState::Succeeded;
+        assert_eq!(find_ready_run(&[1, 2], &[], &runs, &states, &settings, usize::MAX, &capacity), Some(0));
+        assert_eq!(find_ready_run(&[2], &[], &runs, &states, &settings, usize::MAX, &capacity), Some(0));
+    }
+
+    #[test]
+    fn changed_runners() {
+        let runs = vec![test_run(0, vec![], vec![]), test_run(0, vec![], vec![]), test_run(1, vec![], vec![])];
+        let states = vec![RunState::Pending; 3];
+        let mut settings = vec![test_settings(1, false), test_settings(1, true)];
+        assert_eq!(find_ready_run(&[1, 2], &[0], &runs, &states, &settings, 3, &[]), Some(1));
+        // The runners of command 0 follow the total, and the explicit ones of
+        // command 1 are kept.
+        set_runners(&mut settings, 3);
+        assert_eq!(settings[0].runners, 3);
+        assert_eq!(settings[1].runners, 1);
+        assert_eq!(find_ready_run(&[1, 2], &[0], &runs, &states, &settings, 3, &[]), Some(0));
+        assert_eq!(find_ready_run(&[2], &[0, 1], &runs, &states, &settings, 3, &[]), Some(0));
+        set_runners(&mut settings, 0);
+        assert_eq!(find_ready_run(&[1, 2], &[], &runs, &states, &settings, 0, &[]), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exported_script_in_sh() {
+        // The exported script is run by any POSIX shell (e.g. dash).
+        let mut run = test_run(0, vec![], vec![]);
+        run.argv = ["printf", "[%s]", "a\tb", "c\nd", "it's", "$HOME"].map(String::from).to_vec();
+        run.env = vec![("RUNNER_REPLICA".to_string(), "0".to_string())];
+        run.workdir = Some("runs/my run".to_string());
+        let dir = env::temp_dir().join(format!("runner-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = Command::new("sh")
+            .args(["-c", &export::to_shell_script(&[run])])
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "[a\tb][c\nd][it's][$HOME]");
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::process::exit;
+
+use crate::{Grid, read_file_args};
+
+
+/// Check if the argument separates GNU parallel input sources.
+fn is_source_separator(arg: &str) -> bool {
+    arg == ":::" || arg == ":::+" || arg == "::::" || arg == "::::+"
+}
+
+
+/// Parse the GNU parallel input sources of the command arguments (e.g.
+/// `cmd {1} {2} ::: a b ::: x y`), if any, and get the grid of combinations.
+///
+/// - `:::` introduces an input source with the following arguments.
+/// - `::::` introduces an input source for each of the following files, with
+///   one argument per line.
+/// - `:::+` and `::::+` link the input source with the previous one, so that
+///   their values are combined by their position.
+///
+/// The input sources are inserted in the command through the `{1}`, `{2}`...
+/// placeholders (`{}` is the first input source), or appended to the command
+/// if it has no placeholders. The command arguments are replaced by the
+/// command followed by `--`.
+pub(crate) fn parse_parallel_args(command_args: &mut Vec<String>) -> Option<Grid> {
+    let start_idx = command_args.iter().position(|x| is_source_separator(x))?;
+    // The input sources replace the grid arguments, which would be lost.
+    if command_args[..start_idx].iter().any(|x| x == "--") {
+        println!("Error: GNU parallel input sources (:::) cannot be combined with arguments after --.");
+        exit(1);
+    }
+    // Input sources: (values, linked to the previous source).
+    let mut sources: Vec<(Vec<String>, bool)> = Vec::new();
+    let mut separator = "";
+    for arg in &command_args[start_idx..] {
+        if is_source_separator(arg) {
+            separator = arg;
+            if separator == ":::" || separator == ":::+" {
+                sources.push((Vec::new(), separator.ends_with('+')));
+            }
+            continue;
+        }
+        if separator.starts_with("::::") {
+            let file_args = match read_file_args(arg) {
+                Ok(file_args) => file_args,
+                Err(why) => {
+                    println!("Error: couldn't open {}: {}", arg, why);
+                    exit(1);
+                }
+            };
+            let linked = separator.ends_with('+') && sources.last().is_some();
+            sources.push((file_args, linked));
+            // The following files of the same separator are not linked.
+            separator = "::::";
+        } else {
+            sources.last_mut().unwrap().0.push(arg.clone());
+        }
+    }
+    if sources.first().is_some_and(|x| x.1) {
+        println!("Error: the first input source cannot be linked.");
+        exit(1);
+    }
+
+    let mut multi_args = BTreeMap::new();
+    let mut positional_args: BTreeMap<usize, (String, Vec<String>)> = BTreeMap::new();
+    let mut placeholder_args = Vec::new();
+    for (i, (values, linked)) in sources.iter().enumerate() {
+        let name = (i + 1).to_string();
+        if values.is_empty() {
+            println!("Error: input source {} has no values.", name);
+            exit(1);
+        }
+        let next_linked = sources.get(i + 1).is_some_and(|x| x.1);
+        if *linked || next_linked {
+            // Linked sources are combined like positional arguments.
+            if !*linked && !positional_args.is_empty() {
+                println!("Error: only one group of linked input sources is supported.");
+                exit(1);
+            }
+            if let Some((_, first_values)) = positional_args.values().next() {
+                if values.len() != first_values.len() {
+                    println!("Error: linked input sources must have the same number of values.");
+                    exit(1);
+                }
+            }
+            positional_args.insert(i, (name.clone(), values.clone()));
+        } else {
+            multi_args.insert(i, (name.clone(), values.clone()));
+        }
+        placeholder_args.push(name);
+    }
+
+    //// Get the command and insert the placeholders.
+    command_args.truncate(start_idx);
+    for arg in command_args.iter_mut() {
+        *arg = arg.replace("{}", "{1}");
+    }
+    let has_placeholders = command_args.iter()
+        .any(|arg| placeholder_args.iter().any(|x| arg.contains(&format!("{{{}}}", x))));
+    if !has_placeholders {
+        for name in &placeholder_args {
+            command_args.push(format!("{{{}}}", name));
+        }
+    }
+    command_args.push("--".to_string());
+
+    Some(Grid {
+        multi_args,
+        positional_args,
+        command_specific_args: Vec::new(),
+        distributed_args: Vec::new(),
+        joined_args: Vec::new(),
+        placeholder_args,
+        conditional_args: BTreeMap::new(),
+    })
+}
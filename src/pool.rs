@@ -0,0 +1,93 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+
+/// Pool shared by several runner processes, whose slots are lock files in a
+/// common directory (e.g. `/tmp/runner-pools/gpu-box/0.lock`).
+pub(crate) struct SharedPool {
+    pub(crate) name: String,
+    pub(crate) size: usize,
+    dir: PathBuf,
+}
+
+
+/// Parse a shared pool (e.g. `gpu-box:6`) whose lock files are in the
+/// directory of the pools.
+pub(crate) fn parse_shared_pool(arg: &str, pools_dir: &Path) -> SharedPool {
+    let (name, size) = match arg.rsplit_once(':') {
+        Some((name, size)) => (name, size.parse::<usize>()),
+        None => (arg, Ok(0)),
+    };
+    let is_valid_name = !name.is_empty()
+        && name.chars().all(|x| x.is_ascii_alphanumeric() || "-_.".contains(x))
+        && name != "."
+        && name != "..";
+    match size {
+        Ok(size) if size > 0 && is_valid_name => SharedPool {
+            name: name.to_string(),
+            size,
+            dir: pools_dir.join(name),
+        },
+        _ => {
+            println!("Error: --runner-pool requires a <name>:<size> argument (e.g., gpu-box:6).");
+            exit(1);
+        }
+    }
+}
+
+
+/// Create the directory of the lock files of the pool, which can be used by
+/// other users, and the directory of the pools if it does not exist.
+pub(crate) fn create_pool_dir(pool: &SharedPool) {
+    let pools_dir = pool.dir.parent().unwrap();
+    for dir in [pools_dir, &pool.dir] {
+        let exists = dir.is_dir();
+        if let Err(e) = fs::create_dir_all(dir) {
+            println!("Error: couldn't create the directory of the pool {}: {}", dir.display(), e);
+            exit(1);
+        }
+        // The sticky bit, as in /tmp, so that the directories created by a
+        // user can be shared with the others. It can only be set by the owner.
+        #[cfg(unix)]
+        if !exists || dir == pool.dir {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(dir, fs::Permissions::from_mode(0o1777));
+        }
+    }
+}
+
+
+/// Try to lock a free slot of the pool, which is held until the returned file
+/// is closed. The locks are released by the system when a runner exits, even
+/// if it is killed, so there are no stale locks.
+pub(crate) fn try_lock_slot(pool: &SharedPool) -> Option<File> {
+    for slot in 0..pool.size {
+        let lock_path = pool.dir.join(format!("{}.lock", slot));
+        let lock_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path);
+        #[cfg(unix)]
+        if lock_file.is_ok() {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&lock_path, fs::Permissions::from_mode(0o666));
+        }
+        // The lock files of other users may be read-only, which is enough to
+        // lock them.
+        let mut lock_file = match lock_file.or_else(|_| File::open(&lock_path)) {
+            Ok(lock_file) => lock_file,
+            Err(_) => continue,
+        };
+        if lock_file.try_lock().is_ok() {
+            // The holder of the slot, for information.
+            let _ = lock_file.set_len(0);
+            let _ = writeln!(lock_file, "{}", std::process::id());
+            return Some(lock_file);
+        }
+    }
+    None
+}
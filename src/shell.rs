@@ -0,0 +1,133 @@
+use std::ffi::OsStr;
+
+
+fn single_quote(arg: &str) -> String {
+    // Single quotes cannot be escaped inside single quotes.
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+
+/// Quote the bytes with dollar-single-quotes (e.g. `$'\xff'`), escaping the
+/// invalid UTF-8 bytes. This is not POSIX (e.g. dash does not support it), but
+/// bash, zsh and ksh do, and POSIX shells cannot express these bytes otherwise.
+fn dollar_quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("$'");
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => quoted.push_str("\\\\"),
+                '\'' => quoted.push_str("\\'"),
+                c => quoted.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            quoted.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+
+/// Quote the argument for POSIX shells, if needed. Single quotes keep any
+/// character, including newlines and other control characters.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_string();
+    }
+    single_quote(arg)
+}
+
+
+/// Quote the argument for POSIX shells, if needed. The arguments that are not
+/// valid UTF-8 are quoted with `$'...'` escapes, which only some shells (e.g.
+/// bash) support.
+pub(crate) fn shell_quote_os(arg: &OsStr) -> String {
+    match arg.to_str() {
+        Some(arg) => shell_quote(arg),
+        #[cfg(unix)]
+        None => dollar_quote(std::os::unix::ffi::OsStrExt::as_bytes(arg)),
+        #[cfg(not(unix))]
+        None => shell_quote(&arg.to_string_lossy()),
+    }
+}
+
+
+/// Quote the command (program and arguments) so that it can be pasted into a
+/// POSIX shell.
+pub(crate) fn quote_command<S: AsRef<OsStr>>(argv: &[S]) -> String {
+    let mut words = Vec::new();
+    for (i, arg) in argv.iter().enumerate() {
+        let arg = arg.as_ref();
+        // A program with '=' would be interpreted as a variable assignment.
+        if i == 0 && arg.to_str().is_some_and(|x| x.contains('=')) {
+            words.push(single_quote(arg.to_str().unwrap()));
+        } else {
+            words.push(shell_quote_os(arg));
+        }
+    }
+    words.join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_safe_args() {
+        assert_eq!(shell_quote("--lr=0.1"), "--lr=0.1");
+        assert_eq!(shell_quote("data/a_b.csv"), "data/a_b.csv");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn quote_special_chars() {
+        assert_eq!(shell_quote("my model"), "'my model'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("a*b;c"), "'a*b;c'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("é"), "'é'");
+    }
+
+    #[test]
+    fn quote_control_chars() {
+        // Single quotes keep control characters, in any POSIX shell.
+        assert_eq!(shell_quote("a\nb"), "'a\nb'");
+        assert_eq!(shell_quote("a\tb\r"), "'a\tb\r'");
+        assert_eq!(shell_quote("\x1b[0m it's \\"), "'\x1b[0m it'\\''s \\'");
+        assert_eq!(shell_quote("\u{85}"), "'\u{85}'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quoted_args_in_sh() {
+        // The quoted arguments are the same after being parsed by sh.
+        for arg in ["a\tb", "a\nb", "it's", "\u{85}\x1b", "$HOME `x` \\"] {
+            let output = std::process::Command::new("sh")
+                .args(["-c", &format!("printf %s {}", shell_quote(arg))])
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), arg);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quote_invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let arg = OsStr::from_bytes(b"a\xffb\xc3");
+        assert_eq!(shell_quote_os(arg), "$'a\\xffb\\xc3'");
+        let arg = OsStr::from_bytes(b"it's\t\xff\\");
+        assert_eq!(shell_quote_os(arg), "$'it\\'s\t\\xff\\\\'");
+        assert_eq!(shell_quote_os(OsStr::new("a b")), "'a b'");
+    }
+
+    #[test]
+    fn quote_commands() {
+        assert_eq!(quote_command(&["echo", "a b", "c"]), "echo 'a b' c");
+        // Programs with '=' are not variable assignments.
+        assert_eq!(quote_command(&["A=1", "B=2"]), "'A=1' B=2");
+    }
+}
@@ -0,0 +1,288 @@
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+
+pub(crate) const SIGINT: i32 = 2;
+pub(crate) const SIGABRT: i32 = 6;
+pub(crate) const SIGKILL: i32 = 9;
+pub(crate) const SIGSEGV: i32 = 11;
+pub(crate) const SIGTERM: i32 = 15;
+pub(crate) const SIGXCPU: i32 = 24;
+pub(crate) const SIGXFSZ: i32 = 25;
+#[cfg(target_os = "linux")]
+const SIGUSR1: i32 = 10;
+#[cfg(target_os = "linux")]
+const SIGUSR2: i32 = 12;
+#[cfg(all(unix, not(target_os = "linux")))]
+const SIGUSR1: i32 = 30;
+#[cfg(all(unix, not(target_os = "linux")))]
+const SIGUSR2: i32 = 31;
+
+// Resource limits (setrlimit) of Linux, by name.
+pub(crate) const RLIMITS: [(&str, i32); 8] = [
+    ("cpu-time", 0),
+    ("fsize", 1),
+    ("data", 2),
+    ("stack", 3),
+    ("core", 4),
+    ("nproc", 6),
+    ("nofile", 7),
+    ("mem", 9),
+];
+// I/O scheduling classes (ioprio_set), by name.
+pub(crate) const IO_CLASSES: [(&str, i32); 3] = [("realtime", 1), ("best-effort", 2), ("idle", 3)];
+
+
+// Number of 64-bit words of a CPU set (cpu_set_t), for 1024 CPUs.
+#[cfg(target_os = "linux")]
+const CPU_SET_WORDS: usize = 16;
+
+
+#[cfg(unix)]
+extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn getrlimit(resource: i32, rlim: *mut [u64; 2]) -> i32;
+    fn setrlimit(resource: i32, rlim: *const [u64; 2]) -> i32;
+    fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+    fn syscall(number: i64, ...) -> i64;
+    fn sched_getaffinity(pid: i32, cpusetsize: usize, mask: *mut u64) -> i32;
+    fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+}
+
+
+// Number of interrupt signals (SIGINT or SIGTERM) received by runner.
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_signum: i32) {
+    INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+}
+
+
+/// Catch the interrupt signals (SIGINT and SIGTERM) instead of exiting, so
+/// that they can be forwarded to the commands.
+pub(crate) fn catch_interrupts() {
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGINT, handle_interrupt);
+        signal(SIGTERM, handle_interrupt);
+    }
+}
+
+
+/// Get the number of interrupt signals received since the start.
+pub(crate) fn get_interrupts() -> usize {
+    INTERRUPTS.load(Ordering::SeqCst)
+}
+
+
+// Change of the number of runners requested by signals (SIGUSR1 and SIGUSR2)
+// since it was last taken.
+static RUNNERS_CHANGE: AtomicIsize = AtomicIsize::new(0);
+
+
+#[cfg(unix)]
+extern "C" fn handle_runners_change(signum: i32) {
+    let change = if signum == SIGUSR1 { 1 } else { -1 };
+    RUNNERS_CHANGE.fetch_add(change, Ordering::SeqCst);
+}
+
+
+/// Catch SIGUSR1 and SIGUSR2, which increment and decrement the number of
+/// runners, instead of exiting.
+pub(crate) fn catch_runners_changes() {
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGUSR1, handle_runners_change);
+        signal(SIGUSR2, handle_runners_change);
+    }
+}
+
+
+/// Take the change of the number of runners requested since the last call.
+pub(crate) fn take_runners_change() -> isize {
+    RUNNERS_CHANGE.swap(0, Ordering::SeqCst)
+}
+
+
+/// Run the command in its own process group, so that the command and all its
+/// subprocesses (e.g. the commands of a pipeline) can be signaled together.
+pub(crate) fn set_process_group(command_obj: &mut Command) {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command_obj, 0);
+    #[cfg(not(unix))]
+    let _ = command_obj;
+}
+
+
+/// Send the signal to the process group of the child (see
+/// `set_process_group`). Other platforms can only kill the child.
+pub(crate) fn signal_process_group(child: &mut Child, signum: i32) {
+    #[cfg(unix)]
+    unsafe {
+        kill(-(child.id() as i32), signum);
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signum;
+        let _ = child.kill();
+    }
+}
+
+
+/// Get the CPUs that runner can run on (i.e., its affinity mask).
+pub(crate) fn get_allowed_cpus() -> Vec<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut mask = [0u64; CPU_SET_WORDS];
+        let result = unsafe {
+            sched_getaffinity(0, std::mem::size_of_val(&mask), mask.as_mut_ptr())
+        };
+        if result == 0 {
+            return (0..CPU_SET_WORDS * 64)
+                .filter(|cpu| mask[cpu / 64] & (1 << (cpu % 64)) != 0)
+                .collect();
+        }
+    }
+    let cpus = std::thread::available_parallelism().map_or(1, |x| x.get());
+    (0..cpus).collect()
+}
+
+
+/// Run the command on the given CPUs, setting its affinity before it is
+/// executed. Other platforms than Linux ignore the affinity.
+pub(crate) fn set_cpu_affinity(command_obj: &mut Command, cpus: &[usize]) {
+    #[cfg(target_os = "linux")]
+    {
+        let mut mask = [0u64; CPU_SET_WORDS];
+        for cpu in cpus.iter().filter(|x| **x < CPU_SET_WORDS * 64) {
+            mask[cpu / 64] |= 1 << (cpu % 64);
+        }
+        // The closure runs in the child, after the fork, so it only makes
+        // the system call.
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(command_obj, move || {
+                if sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (command_obj, cpus);
+}
+
+
+/// Limits of the processes of the commands, set before they are executed.
+pub(crate) struct ProcessLimits {
+    // Resource limits: (resource, soft limit).
+    pub(crate) rlimits: Vec<(i32, u64)>,
+    pub(crate) nice: Option<i32>,
+    // I/O priority: (class, level).
+    pub(crate) io_priority: Option<(i32, i32)>,
+}
+
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYS_IOPRIO_SET: i64 = 251;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const SYS_IOPRIO_SET: i64 = 30;
+
+
+/// Set the I/O priority of the current process, where supported.
+#[cfg(target_os = "linux")]
+fn set_io_priority(class: i32, level: i32) -> i64 {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    unsafe {
+        // IOPRIO_WHO_PROCESS, with the class in the upper bits.
+        syscall(SYS_IOPRIO_SET, 1, 0, (class << 13) | level)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let _ = (class, level);
+        0
+    }
+}
+
+
+/// Set the resource limits, the niceness and the I/O priority of the command
+/// before it is executed. Other platforms than Linux ignore the limits.
+pub(crate) fn set_process_limits(command_obj: &mut Command, limits: &ProcessLimits) {
+    #[cfg(target_os = "linux")]
+    {
+        let rlimits = limits.rlimits.clone();
+        let nice = limits.nice;
+        let io_priority = limits.io_priority;
+        // The closure runs in the child, after the fork, so it only makes
+        // system calls.
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(command_obj, move || {
+                for (resource, limit) in &rlimits {
+                    // Only the soft limit is set, which cannot exceed the
+                    // hard limit.
+                    let mut rlimit = [0u64; 2];
+                    if getrlimit(*resource, &mut rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    rlimit[0] = (*limit).min(rlimit[1]);
+                    if setrlimit(*resource, &rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                // PRIO_PROCESS of the current process.
+                if let Some(nice) = nice {
+                    if setpriority(0, 0, nice) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if let Some((class, level)) = io_priority {
+                    if set_io_priority(class, level) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (command_obj, limits);
+}
+
+
+/// Get the signal that terminated the command, if any.
+pub(crate) fn get_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    return std::os::unix::process::ExitStatusExt::signal(status);
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+
+/// Get the load average of the last minute, where available (Linux).
+pub(crate) fn get_load_average() -> Option<f64> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+
+/// Get the total and the available memory in bytes, where available (Linux).
+pub(crate) fn get_memory_info() -> Option<(f64, f64)> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let get_field = |name: &str| -> Option<f64> {
+        let line = meminfo.lines().find(|x| x.starts_with(name))?;
+        let kilobytes: f64 = line[name.len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kilobytes * 1024.0)
+    };
+    Some((get_field("MemTotal:")?, get_field("MemAvailable:")?))
+}
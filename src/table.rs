@@ -0,0 +1,436 @@
+use std::fs;
+use std::process::exit;
+
+use crate::{FLAG_VALUE, SEPARATOR};
+
+
+/// Column of the tables with the index of the command of each run.
+pub(crate) const COMMAND_COLUMN: &str = "runner_command";
+/// Column of the tables with the replica of each run (e.g. exported with
+/// `--runner-export csv`), which is ignored, since the replicas are given by
+/// `--runner-repeat`.
+pub(crate) const REPLICA_COLUMN: &str = "runner_replica";
+
+
+/// A run of a table: the index of its command (all the commands if `None`)
+/// and its combination of arguments.
+pub(crate) type TableRun = (Option<usize>, Vec<(String, String)>);
+
+
+/// Parse CSV records (RFC 4180): fields separated by commas, and optionally
+/// enclosed in double quotes, with `""` as an escaped quote.
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' {
+            in_quotes = true;
+        } else if c == ',' {
+            record.push(field);
+            field = String::new();
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            record.push(field);
+            field = String::new();
+            records.push(record);
+            record = Vec::new();
+        } else {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    // Ignore empty lines.
+    records.retain(|x| !(x.len() == 1 && x[0].is_empty()));
+    records
+}
+
+
+/// Value of a JSON object.
+enum JsonValue {
+    Null,
+    Bool(bool),
+    // Numbers and strings are kept as their string representation.
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+
+/// Minimal parser of flat JSON objects, whose values are strings, numbers,
+/// booleans, null or arrays of strings and numbers.
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|x| x.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}', found '{}'", expected, c)),
+            None => Err(format!("expected '{}', found end of line", expected)),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let mut code = self.parse_unicode_escape()?;
+                            // Characters outside the Basic Multilingual Plane
+                            //   are escaped as UTF-16 surrogate pairs.
+                            if (0xD800..0xDC00).contains(&code) {
+                                if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                                    return Err(format!("unpaired surrogate: \\u{:04x}", code));
+                                }
+                                let low_code = self.parse_unicode_escape()?;
+                                if !(0xDC00..0xE000).contains(&low_code) {
+                                    return Err(format!("unpaired surrogate: \\u{:04x}", code));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low_code - 0xDC00);
+                            }
+                            char::from_u32(code)
+                                .ok_or(format!("invalid unicode escape: \\u{:04x}", code))?
+                        },
+                        Some(c) => c,
+                        None => return Err("unterminated string".to_string()),
+                    };
+                    string.push(escaped);
+                },
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// Parse the 4 hexadecimal digits of a unicode escape (after `\u`).
+    fn parse_unicode_escape(&mut self) -> Result<u32, String> {
+        let code: String = self.chars.by_ref().take(4).collect();
+        if code.len() != 4 || !code.chars().all(|x| x.is_ascii_hexdigit()) {
+            return Err(format!("invalid unicode escape: \\u{}", code));
+        }
+        Ok(u32::from_str_radix(&code, 16).unwrap())
+    }
+
+    /// Parse a number or a literal (true, false or null).
+    fn parse_literal(&mut self) -> String {
+        let mut literal = String::new();
+        while let Some(c) = self.chars.peek() {
+            if c.is_alphanumeric() || "+-.".contains(*c) {
+                literal.push(*c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        literal
+    }
+
+    fn parse_scalar(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'"') {
+            return Ok(JsonValue::Scalar(self.parse_string()?));
+        }
+        let literal = self.parse_literal();
+        match literal.as_str() {
+            "null" => Ok(JsonValue::Null),
+            "true" => Ok(JsonValue::Bool(true)),
+            "false" => Ok(JsonValue::Bool(false)),
+            _ if literal.parse::<f64>().is_ok() => Ok(JsonValue::Scalar(literal)),
+            _ => Err(format!("invalid value: {}", literal)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        if self.chars.peek() != Some(&'[') {
+            return self.parse_scalar();
+        }
+        self.chars.next();
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(JsonValue::Array(values));
+        }
+        loop {
+            match self.parse_scalar()? {
+                JsonValue::Scalar(value) => values.push(value),
+                _ => return Err("arrays can only contain strings and numbers".to_string()),
+            }
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(JsonValue::Array(values)),
+                _ => return Err("expected ',' or ']' in array".to_string()),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Vec<(String, JsonValue)>, String> {
+        let mut object = Vec::new();
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(object);
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            object.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(object),
+                _ => return Err("expected ',' or '}' in object".to_string()),
+            }
+        }
+    }
+}
+
+
+fn parse_command_index(table_path: &str, row: usize, value: &str) -> Option<usize> {
+    if value.is_empty() {
+        return None;
+    }
+    match value.parse() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            println!(
+                "Error: invalid command index '{}' in row {} of {}.",
+                value, row, table_path
+            );
+            exit(1);
+        }
+    }
+}
+
+
+fn read_csv_table(table_path: &str, content: &str) -> Vec<TableRun> {
+    let records = parse_csv(content);
+    let (header, rows) = match records.split_first() {
+        Some((header, rows)) => (header, rows),
+        None => return Vec::new(),
+    };
+    let mut runs = Vec::new();
+    for (row_idx, row) in rows.iter().enumerate() {
+        if row.len() != header.len() {
+            println!(
+                "Error: row {} of {} has {} columns, but the header has {}.",
+                row_idx + 1, table_path, row.len(), header.len()
+            );
+            exit(1);
+        }
+        let mut command_idx = None;
+        let mut combination = Vec::new();
+        for (option, value) in header.iter().zip(row) {
+            if option == COMMAND_COLUMN {
+                command_idx = parse_command_index(table_path, row_idx + 1, value);
+            } else if option == REPLICA_COLUMN {
+                continue;
+            } else if value == FLAG_VALUE {
+                // Flags of the tables exported with `--runner-export csv`.
+                combination.push((option.clone(), String::new()));
+            } else if !value.is_empty() {
+                // Empty cells mean that the option is omitted. Values of
+                //   exported tables with several arguments are separated by
+                //   `[BREAK!]`, as the values of the grids.
+                combination.push((option.clone(), value.clone()));
+            }
+        }
+        runs.push((command_idx, combination));
+    }
+    runs
+}
+
+
+fn read_jsonl_table(table_path: &str, content: &str) -> Vec<TableRun> {
+    let mut runs = Vec::new();
+    for (line_idx, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut parser = JsonParser { chars: line.chars().peekable() };
+        let object = match parser.parse_object() {
+            Ok(object) => object,
+            Err(why) => {
+                println!("Error: invalid JSON in line {} of {}: {}", line_idx + 1, table_path, why);
+                exit(1);
+            }
+        };
+        let mut command_idx = None;
+        let mut combination = Vec::new();
+        for (option, value) in object {
+            match value {
+                JsonValue::Scalar(value) if option == COMMAND_COLUMN => {
+                    command_idx = parse_command_index(table_path, line_idx + 1, &value);
+                },
+                _ if option == REPLICA_COLUMN => (),
+                // True booleans are flags, and null or false values are omitted.
+                JsonValue::Bool(true) => combination.push((option, String::new())),
+                JsonValue::Null | JsonValue::Bool(false) => (),
+                JsonValue::Scalar(value) => {
+                    if !value.is_empty() {
+                        combination.push((option, value));
+                    }
+                },
+                // Array values are passed as multiple arguments.
+                JsonValue::Array(values) => {
+                    if !values.is_empty() {
+                        combination.push((option, values.join(SEPARATOR)));
+                    }
+                },
+            }
+        }
+        runs.push((command_idx, combination));
+    }
+    runs
+}
+
+
+/// Read a table of runs from a CSV file (with the options in the header) or
+/// a JSONL file (with one object per run), depending on the file extension.
+pub(crate) fn read_table(table_path: &str) -> Vec<TableRun> {
+    let content = match fs::read_to_string(table_path) {
+        Ok(content) => content,
+        Err(why) => {
+            println!("Error: couldn't read {}: {}", table_path, why);
+            exit(1);
+        }
+    };
+    if table_path.ends_with(".jsonl") || table_path.ends_with(".json") {
+        read_jsonl_table(table_path, &content)
+    } else {
+        read_csv_table(table_path, &content)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_json(line: &str) -> Result<Vec<(String, JsonValue)>, String> {
+        JsonParser { chars: line.chars().peekable() }.parse_object()
+    }
+
+    fn parse_json_string(value: &str) -> Result<String, String> {
+        match parse_json(&format!("{{\"key\": {}}}", value))?.pop() {
+            Some((_, JsonValue::Scalar(string))) => Ok(string),
+            _ => Err("not a string".to_string()),
+        }
+    }
+
+    #[test]
+    fn csv_quoted_fields() {
+        let records = parse_csv("a,b,c\n\"1,2\",\"say \"\"hi\"\"\",\n");
+        assert_eq!(records, vec![
+            vec!["a", "b", "c"],
+            vec!["1,2", "say \"hi\"", ""],
+        ]);
+    }
+
+    #[test]
+    fn csv_newlines() {
+        // Quoted newlines are part of the field, and CRLF ends the records.
+        let records = parse_csv("a,b\r\n\"line 1\nline 2\",x\r\n\r\n\n3,4");
+        assert_eq!(records, vec![
+            vec!["a", "b"],
+            vec!["line 1\nline 2", "x"],
+            vec!["3", "4"],
+        ]);
+    }
+
+    #[test]
+    fn csv_table() {
+        let runs = read_csv_table("runs.csv", "runner_command,--a,--b,--c\n1,true,[FLAG!],\n,false,x,y[BREAK!]z\n");
+        assert_eq!(runs[0], (Some(1), vec![
+            ("--a".to_string(), "true".to_string()),
+            ("--b".to_string(), String::new()),
+        ]));
+        assert_eq!(runs[1], (None, vec![
+            ("--a".to_string(), "false".to_string()),
+            ("--b".to_string(), "x".to_string()),
+            ("--c".to_string(), format!("y{}z", SEPARATOR)),
+        ]));
+    }
+
+    #[test]
+    fn csv_empty() {
+        assert!(parse_csv("").is_empty());
+        assert!(parse_csv("\n\n").is_empty());
+    }
+
+    #[test]
+    fn json_values() {
+        let object = parse_json(r#"{"a": "x", "b": 1.5e3, "c": true, "d": null, "e": ["y", 2], "f": []}"#).unwrap();
+        let keys: Vec<&str> = object.iter().map(|x| x.0.as_str()).collect();
+        assert_eq!(keys, ["a", "b", "c", "d", "e", "f"]);
+        assert!(matches!(&object[0].1, JsonValue::Scalar(x) if x == "x"));
+        assert!(matches!(&object[1].1, JsonValue::Scalar(x) if x == "1.5e3"));
+        assert!(matches!(object[2].1, JsonValue::Bool(true)));
+        assert!(matches!(object[3].1, JsonValue::Null));
+        assert!(matches!(&object[4].1, JsonValue::Array(x) if x == &["y", "2"]));
+        assert!(matches!(&object[5].1, JsonValue::Array(x) if x.is_empty()));
+        assert!(parse_json("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(parse_json_string(r#""a\"b\\c\/d\n\t""#).unwrap(), "a\"b\\c/d\n\t");
+        assert_eq!(parse_json_string(r#""\u00e9\u4E2D""#).unwrap(), "\u{e9}\u{4e2d}");
+        // Surrogate pairs.
+        assert_eq!(parse_json_string(r#""\ud83d\ude00""#).unwrap(), "\u{1f600}");
+        assert!(parse_json_string(r#""\ud83d""#).is_err());
+        assert!(parse_json_string(r#""\ud83dx""#).is_err());
+        assert!(parse_json_string(r#""\u12""#).is_err());
+        assert!(parse_json_string(r#""\u12g4""#).is_err());
+    }
+
+    #[test]
+    fn json_errors() {
+        assert!(parse_json(r#"{"a": "x""#).is_err());
+        assert!(parse_json(r#"{"a": "x"#).is_err());
+        assert!(parse_json(r#"{"a" "x"}"#).is_err());
+        assert!(parse_json(r#"{"a": yes}"#).is_err());
+        assert!(parse_json(r#"{"a": [{"b": 1}]}"#).is_err());
+        assert!(parse_json(r#"["a"]"#).is_err());
+    }
+}
@@ -87,3 +87,10 @@ pub(crate) fn get_date_time_string(now: SystemTime) -> String {
         );
     date_time_string
 }
+
+
+/// Date and time as a relative path that is safe for file systems (e.g.,
+/// `2024-05-01/13-45-10`).
+pub(crate) fn get_date_time_path(now: SystemTime) -> String {
+    get_date_time_string(now).replace(' ', "/").replace(':', "-")
+}